@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+/// Who may walk along a way, based on `foot=*` and `access=*`. Ordered from least to most
+/// restrictive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Access {
+    /// Anyone can walk here
+    Public,
+    /// Walkable, but with a cost penalty, like a path through private land that the owner allows
+    Permissive,
+    /// Only useful to start or end a route, like a private driveway or a shop's car park. Routing
+    /// through these has a big penalty.
+    Destination,
+    /// Not walkable at all
+    Excluded,
+}
+
+/// Maps tag values to the access decision. Studies can override this in their profile.
+pub fn default_access_values() -> BTreeMap<String, Access> {
+    let mut values = BTreeMap::new();
+    for (value, access) in [
+        ("yes", Access::Public),
+        ("public", Access::Public),
+        ("designated", Access::Public),
+        ("official", Access::Public),
+        ("permissive", Access::Permissive),
+        ("permit", Access::Permissive),
+        ("destination", Access::Destination),
+        ("customers", Access::Destination),
+        ("delivery", Access::Destination),
+        ("private", Access::Excluded),
+        ("no", Access::Excluded),
+    ] {
+        values.insert(value.to_string(), access);
+    }
+    values
+}
+
+/// `foot=*` takes precedence over `access=*`. Values not in the table are ignored, and ways
+/// without any recognized values are public.
+pub fn classify_access(values: &BTreeMap<String, Access>, tags: &Tags) -> Access {
+    tagged_access(values, tags).unwrap_or(Access::Public)
+}
+
+/// Like `classify_access`, but `None` if there are no recognized values
+pub fn tagged_access(values: &BTreeMap<String, Access>, tags: &Tags) -> Option<Access> {
+    ["foot", "access"]
+        .into_iter()
+        .find_map(|key| tags.get(key).and_then(|v| values.get(v)).copied())
+}
@@ -0,0 +1,120 @@
+use geo::{BoundingRect, Contains, Coord, Distance, Euclidean, Length, Line, LineString, Polygon};
+use osm_reader::WayID;
+use rstar::primitives::GeomWithData;
+use rstar::{AABB, RTree};
+use utils::Tags;
+use utils::osm2graph::{Graph, IntersectionID};
+
+use crate::create::add_edge;
+use crate::levels::{compatible, levels};
+use crate::{Profile, RoadKind};
+
+/// Ways entering an area usually end on its outline, or just short of it
+const ENTRY_TOLERANCE_M: f64 = 1.0;
+
+/// Is this way or multipolygon relation an open space people can walk straight across? Indoor
+/// corridors and halls in stations and malls count too.
+pub fn is_pedestrian_area(tags: &Tags) -> bool {
+    (tags.is("highway", "pedestrian")
+        && (tags.is("area", "yes") || tags.is("type", "multipolygon")))
+        || tags.is("place", "square")
+        || tags.is_any("indoor", vec!["corridor", "area"])
+}
+
+// Squares, plazas, and station forecourts are mapped as areas, but people walk straight across
+// them. Find every intersection on the outline or inside of an area with a walkable edge, then
+// connect every pair that can see each other across the area. The connections keep the area's
+// way and tags, so the profile classifies them as PedestrianArea, or can exclude them.
+pub fn connect_pedestrian_areas(
+    graph: &mut Graph,
+    profile: &Profile,
+    areas: Vec<(WayID, Tags, Polygon)>,
+) {
+    let intersections = RTree::bulk_load(
+        graph
+            .intersections
+            .values()
+            .map(|i| GeomWithData::new([i.point.x(), i.point.y()], i.id))
+            .collect(),
+    );
+    let mut num_connections = 0;
+    for (way, mut tags, mut polygon) in areas {
+        tags.insert("highway", "pedestrian");
+        tags.insert("area", "yes");
+        if profile.classify(&tags) != Some(RoadKind::PedestrianArea) {
+            continue;
+        }
+        graph.mercator.to_mercator_in_place(&mut polygon);
+        let area_levels = levels(&tags);
+
+        let Some(bbox) = polygon.bounding_rect() else {
+            continue;
+        };
+        let envelope = AABB::from_corners(
+            [
+                bbox.min().x - ENTRY_TOLERANCE_M,
+                bbox.min().y - ENTRY_TOLERANCE_M,
+            ],
+            [
+                bbox.max().x + ENTRY_TOLERANCE_M,
+                bbox.max().y + ENTRY_TOLERANCE_M,
+            ],
+        );
+        let mut entries: Vec<(IntersectionID, Coord)> = Vec::new();
+        for obj in intersections.locate_in_envelope(&envelope) {
+            let i = &graph.intersections[&obj.data];
+            if Euclidean.distance(&polygon, &i.point) > ENTRY_TOLERANCE_M {
+                continue;
+            }
+            // Only connect ways on the same level as the area
+            if i.edges.iter().any(|e| {
+                let tags = &graph.edges[e].osm_tags;
+                matches!(profile.classify(tags), Some(kind) if kind != RoadKind::Severance)
+                    && compatible(&area_levels, &levels(tags))
+            }) {
+                entries.push((i.id, i.point.into()));
+            }
+        }
+        if entries.len() > 100 {
+            warn!(
+                "Pedestrian area {:?} has {} entrances; connecting all of them is slow",
+                tags.get("name"),
+                entries.len()
+            );
+        }
+
+        for (idx, (i1, pt1)) in entries.iter().enumerate() {
+            for (i2, pt2) in &entries[idx + 1..] {
+                let line = Line::new(*pt1, *pt2);
+                if !visible_across(&polygon, line) {
+                    continue;
+                }
+                add_edge(
+                    graph,
+                    way,
+                    *i1,
+                    *i2,
+                    LineString::new(vec![*pt1, *pt2]),
+                    tags.clone(),
+                );
+                num_connections += 1;
+            }
+        }
+    }
+    if num_connections > 0 {
+        info!("Added {num_connections} connections across pedestrian areas");
+    }
+}
+
+/// Does a straight line between two entries stay inside the area? The ends are allowed to be just
+/// outside.
+fn visible_across(polygon: &Polygon, line: Line) -> bool {
+    let length = Euclidean.length(&line);
+    if length <= 2.0 * ENTRY_TOLERANCE_M {
+        return true;
+    }
+    let trim = ENTRY_TOLERANCE_M / length;
+    let delta = line.delta();
+    let inner = Line::new(line.start + delta * trim, line.end - delta * trim);
+    polygon.contains(&inner)
+}
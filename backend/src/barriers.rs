@@ -0,0 +1,283 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use geo::{BoundingRect, Coord, Geometry, Intersects, LineString, Point};
+use graph::{Graph, RoadID};
+use osm_reader::{NodeID, WayID};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{AABB, RTree};
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::access::tagged_access;
+use crate::{Access, Profile};
+
+/// Barrier types that stop people walking through them, unless tagged otherwise
+const BLOCKING: [&str; 8] = [
+    "fence",
+    "wall",
+    "hedge",
+    "city_wall",
+    "retaining_wall",
+    "guard_rail",
+    "jersey_barrier",
+    "ditch",
+];
+
+/// A barrier node or linear barrier that restricts walking along some roads
+#[derive(Serialize, Deserialize)]
+pub struct Barrier {
+    /// A link to the OSM node or way
+    pub url: String,
+    /// In Mercator. A point for barrier nodes, or a line for linear barriers.
+    pub geometry: Geometry,
+    pub tags: Tags,
+    pub access: Access,
+    /// The roads this barrier restricts
+    pub roads: BTreeSet<RoadID>,
+}
+
+/// How walking through a barrier is restricted, or `None` if it isn't. `locked=yes` always
+/// blocks, then `foot=*` and `access=*` are used, and otherwise it depends on the type of barrier.
+pub fn barrier_access(profile: &Profile, tags: &Tags) -> Option<Access> {
+    if tags.is("locked", "yes") {
+        return Some(Access::Excluded);
+    }
+    let access = tagged_access(&profile.access, tags).unwrap_or_else(|| {
+        if tags.is_any("barrier", BLOCKING.to_vec()) {
+            Access::Excluded
+        } else {
+            Access::Public
+        }
+    });
+    (access != Access::Public).then_some(access)
+}
+
+/// One road touching a barrier node: its ID, OSM way, tags, and whether the node is inside the road
+/// rather than at one of its ends
+pub type TouchingRoad<'a> = (RoadID, WayID, &'a Tags, bool);
+
+/// Decides which roads touching a barrier node it restricts. Where several ways meet at the node,
+/// like a gate where a private driveway joins a street, the barrier only controls some of them:
+/// the ways with the same tagged access as the barrier, or failing that, the ways that end at the
+/// barrier instead of passing through it.
+pub fn controlled_roads(
+    profile: &Profile,
+    barrier_tags: &Tags,
+    touching: &[TouchingRoad],
+) -> HashSet<RoadID> {
+    let mut per_way: HashMap<WayID, Vec<&TouchingRoad>> = HashMap::new();
+    for road in touching {
+        per_way.entry(road.1).or_default().push(road);
+    }
+    let all = || touching.iter().map(|road| road.0).collect();
+    if per_way.len() <= 1 {
+        return all();
+    }
+    let pick = |keep: &dyn Fn(&[&TouchingRoad]) -> bool| -> Option<HashSet<RoadID>> {
+        let ways: Vec<_> = per_way.values().filter(|roads| keep(roads)).collect();
+        if ways.is_empty() || ways.len() == per_way.len() {
+            return None;
+        }
+        Some(ways.into_iter().flatten().map(|road| road.0).collect())
+    };
+
+    let access = barrier_access(profile, barrier_tags);
+    let matching_access =
+        pick(&|roads| access.is_some() && tagged_access(&profile.access, roads[0].2) == access);
+    // A way passes through if the node is inside one of its roads, or splits it into two
+    let ends_here = pick(&|roads| roads.len() == 1 && !roads[0].3);
+    matching_access.or(ends_here).unwrap_or_else(all)
+}
+
+/// Turns scraped barriers into the ones that restrict some road. Barrier nodes already know the
+/// roads they're on. Linear barriers restrict roads that cross them without sharing a node, like a
+/// path mapped straight through a fence; a shared node is a gap or a gate, which is handled as a
+/// barrier node if it's tagged.
+pub fn collect_barriers(
+    graph: &Graph,
+    profile: &Profile,
+    nodes: Vec<(NodeID, Coord, Tags, HashSet<RoadID>)>,
+    lines: Vec<(WayID, Vec<NodeID>, LineString, Tags, HashSet<RoadID>)>,
+) -> Vec<Barrier> {
+    let mut barriers = Vec::new();
+    for (id, pt, tags, roads) in nodes {
+        let Some(access) = barrier_access(profile, &tags) else {
+            continue;
+        };
+        barriers.push(Barrier {
+            url: id.to_string(),
+            geometry: Point::from(graph.mercator.pt_to_mercator(pt)).into(),
+            tags,
+            access,
+            roads: roads.into_iter().collect(),
+        });
+    }
+
+    if lines.is_empty() {
+        return barriers;
+    }
+    let rtree = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .map(|r| {
+                let bbox = r.linestring.bounding_rect().unwrap();
+                GeomWithData::new(
+                    Rectangle::from_corners(bbox.min().x_y().into(), bbox.max().x_y().into()),
+                    r.id,
+                )
+            })
+            .collect(),
+    );
+    for (id, _, mut linestring, tags, shared) in lines {
+        let Some(access) = barrier_access(profile, &tags) else {
+            continue;
+        };
+        graph.mercator.to_mercator_in_place(&mut linestring);
+        let Some(bbox) = linestring.bounding_rect() else {
+            continue;
+        };
+        let layer = tags.get("layer").map(|x| x.as_str()).unwrap_or("0");
+
+        let mut roads = BTreeSet::new();
+        for obj in rtree.locate_in_envelope_intersecting(&AABB::from_corners(
+            bbox.min().x_y().into(),
+            bbox.max().x_y().into(),
+        )) {
+            let road = &graph.roads[obj.data.0];
+            if shared.contains(&road.id)
+                || road
+                    .osm_tags
+                    .get("layer")
+                    .map(|x| x.as_str())
+                    .unwrap_or("0")
+                    != layer
+                || ["bridge", "tunnel"]
+                    .iter()
+                    .any(|k| road.osm_tags.has(k) && !road.osm_tags.is(k, "no"))
+            {
+                continue;
+            }
+            if road.linestring.intersects(&linestring) {
+                roads.insert(road.id);
+            }
+        }
+        if roads.is_empty() {
+            continue;
+        }
+        barriers.push(Barrier {
+            url: id.to_string(),
+            geometry: linestring.into(),
+            tags,
+            access,
+            roads,
+        });
+    }
+    barriers
+}
+
+/// Makes each road at least as restricted as the barriers on it, returning the roads that
+/// changed. Since the routing profiles only see a road's own tags, the caller has to remove roads
+/// that're now excluded.
+pub fn restrict_roads(access: &mut [Access], barriers: &[Barrier]) -> BTreeSet<RoadID> {
+    let mut changed = BTreeSet::new();
+    for barrier in barriers {
+        for r in &barrier.roads {
+            if barrier.access > access[r.0] {
+                access[r.0] = barrier.access;
+                changed.insert(*r);
+            }
+        }
+    }
+    if !changed.is_empty() {
+        info!("Barriers restrict {} roads", changed.len());
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_barrier_access() {
+        let profile = Profile::builtin("SeparateWays").unwrap();
+        for (input, expected) in [
+            (vec!["barrier=gate"], None),
+            (vec!["barrier=gate", "locked=yes"], Some(Access::Excluded)),
+            (
+                vec!["barrier=gate", "access=private"],
+                Some(Access::Excluded),
+            ),
+            (
+                vec!["barrier=gate", "access=private", "foot=permissive"],
+                Some(Access::Permissive),
+            ),
+            (vec!["barrier=fence"], Some(Access::Excluded)),
+            (vec!["barrier=fence", "foot=yes"], None),
+            (vec!["barrier=bollard"], None),
+        ] {
+            assert_eq!(
+                barrier_access(&profile, &tags(input.clone())),
+                expected,
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_controlled_roads() {
+        let profile = Profile::builtin("SeparateWays").unwrap();
+        let gate = tags(vec!["barrier=gate", "locked=yes"]);
+        let street = tags(vec!["highway=residential"]);
+        let driveway = tags(vec!["highway=service", "service=driveway"]);
+        let private = tags(vec!["highway=service", "access=private"]);
+        let (way1, way2) = (WayID(1), WayID(2));
+
+        // On the interior of one road
+        assert_eq!(
+            controlled_roads(&profile, &gate, &[(RoadID(0), way1, &driveway, true)]),
+            HashSet::from([RoadID(0)])
+        );
+        // Where a driveway ends at a street, only the driveway
+        assert_eq!(
+            controlled_roads(
+                &profile,
+                &gate,
+                &[
+                    (RoadID(0), way1, &street, false),
+                    (RoadID(1), way1, &street, false),
+                    (RoadID(2), way2, &driveway, false),
+                ]
+            ),
+            HashSet::from([RoadID(2)])
+        );
+        // Two ways ending at the gate, one with matching access
+        let private_gate = tags(vec!["barrier=gate", "access=private"]);
+        assert_eq!(
+            controlled_roads(
+                &profile,
+                &private_gate,
+                &[
+                    (RoadID(0), way1, &street, false),
+                    (RoadID(1), way2, &private, false),
+                ]
+            ),
+            HashSet::from([RoadID(1)])
+        );
+        // Nothing to tell them apart
+        assert_eq!(
+            controlled_roads(
+                &profile,
+                &gate,
+                &[
+                    (RoadID(0), way1, &street, false),
+                    (RoadID(1), way2, &street, false),
+                ]
+            )
+            .len(),
+            2
+        );
+    }
+}
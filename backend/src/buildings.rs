@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use geo::{
+    BoundingRect, Closest, ClosestPoint, Coord, Euclidean, InteriorPoint, Intersects, Length, Line,
+    LineString, Point, Polygon,
+};
+use graph::{Direction, Graph, RoadID};
+use rstar::primitives::GeomWithData;
+use rstar::{AABB, RTree};
+use serde::{Deserialize, Serialize};
+
+use crate::RoadKind;
+
+/// Don't look any further than this for a road to assign a building to
+const MAX_SEARCH_M: f64 = 500.0;
+/// Buildings assigned to a road further than this are reported as problems
+const FAR_M: f64 = 100.0;
+
+/// A building outline with its holes, and any entrances mapped on it. In WGS84.
+pub struct ScrapedBuilding {
+    pub polygon: Polygon,
+    pub entrances: Vec<Coord>,
+}
+
+/// A building that couldn't be assigned to a road for some profile, or was assigned far away
+#[derive(Serialize, Deserialize)]
+pub struct BuildingProblem {
+    /// Index into `MapModel::buildings`
+    pub building: usize,
+    pub profile: String,
+    /// The distance to the assigned road, or `None` if there wasn't one
+    pub distance: Option<f64>,
+    /// From where the building was assigned to the road, in Mercator
+    pub connector: Option<LineString>,
+}
+
+pub struct Assignment {
+    /// In Mercator
+    pub buildings: Vec<Polygon>,
+    /// Indexed by ProfileID, with indices into `buildings`
+    pub buildings_per_road: Vec<HashMap<RoadID, Vec<usize>>>,
+    pub problems: Vec<BuildingProblem>,
+}
+
+// Assign each building to the road people reach it from, separately for each profile, since they
+// can use different roads. Entrances say which side of a building people actually leave from.
+// Otherwise, use a point inside the building. Then look for the closest road that isn't on the
+// other side of a severance or a blocking linear barrier, judged by a straight line to the road.
+pub fn assign_buildings(
+    graph: &Graph,
+    road_kinds: &[RoadKind],
+    blocking_lines: Vec<LineString>,
+    scraped: Vec<ScrapedBuilding>,
+) -> Assignment {
+    // Nobody lives in an elevator
+    let roads = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .filter(|r| road_kinds[r.id.0] != RoadKind::Elevator)
+            .flat_map(|r| {
+                r.linestring
+                    .lines()
+                    .map(|line| GeomWithData::new(line, r.id))
+            })
+            .collect(),
+    );
+    // Severances and barriers. Connecting to a severance itself is fine.
+    let obstacles = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .filter(|r| road_kinds[r.id.0] == RoadKind::Severance)
+            .flat_map(|r| {
+                r.linestring
+                    .lines()
+                    .map(|line| GeomWithData::new(line, Some(r.id)))
+            })
+            .chain(
+                blocking_lines
+                    .iter()
+                    .flat_map(|ls| ls.lines().map(|line| GeomWithData::new(line, None))),
+            )
+            .collect(),
+    );
+
+    let mut profiles: Vec<_> = graph.profile_names.iter().collect();
+    profiles.sort_by_key(|(_, profile)| profile.0);
+
+    let mut assignment = Assignment {
+        buildings: Vec::new(),
+        buildings_per_road: vec![HashMap::new(); profiles.len()],
+        problems: Vec::new(),
+    };
+    for mut b in scraped {
+        graph.mercator.to_mercator_in_place(&mut b.polygon);
+        let mut anchors: Vec<Coord> = b
+            .entrances
+            .iter()
+            .map(|pt| graph.mercator.pt_to_mercator(*pt))
+            .collect();
+        // The interior point only fails for degenerate polygons
+        anchors.push(
+            b.polygon
+                .interior_point()
+                .map(|pt| pt.into())
+                .unwrap_or_else(|| *b.polygon.exterior().coords().next().unwrap()),
+        );
+        let idx = assignment.buildings.len();
+
+        for (name, profile) in &profiles {
+            let usable = |r: RoadID| graph.roads[r.0].access[profile.0] != Direction::None;
+            // Only fall back to the interior point if no entrance works
+            let (interior, entrances) = anchors.split_last().unwrap();
+            let best = entrances
+                .iter()
+                .filter_map(|pt| closest_reachable(&roads, &obstacles, *pt, &usable))
+                .min_by(|a, b| Euclidean.length(&a.1).total_cmp(&Euclidean.length(&b.1)))
+                .or_else(|| closest_reachable(&roads, &obstacles, *interior, &usable));
+
+            match best {
+                Some((road, connector)) => {
+                    let distance = Euclidean.length(&connector);
+                    if distance > FAR_M {
+                        assignment.problems.push(BuildingProblem {
+                            building: idx,
+                            profile: name.to_string(),
+                            distance: Some(distance),
+                            connector: Some(connector.into()),
+                        });
+                    }
+                    assignment.buildings_per_road[profile.0]
+                        .entry(road)
+                        .or_default()
+                        .push(idx);
+                }
+                None => {
+                    assignment.problems.push(BuildingProblem {
+                        building: idx,
+                        profile: name.to_string(),
+                        distance: None,
+                        connector: None,
+                    });
+                }
+            }
+        }
+        assignment.buildings.push(b.polygon);
+    }
+
+    if !assignment.problems.is_empty() {
+        warn!(
+            "{} buildings couldn't be assigned to a nearby road for some profile",
+            assignment.problems.len()
+        );
+    }
+    assignment
+}
+
+/// Finds the closest usable road from a point without crossing an obstacle, returning the road and
+/// a straight line to it
+fn closest_reachable(
+    roads: &RTree<GeomWithData<Line, RoadID>>,
+    obstacles: &RTree<GeomWithData<Line, Option<RoadID>>>,
+    pt: Coord,
+    usable: &impl Fn(RoadID) -> bool,
+) -> Option<(RoadID, Line)> {
+    for (segment, dist_squared) in roads.nearest_neighbor_iter_with_distance_2(&Point::from(pt)) {
+        if dist_squared > MAX_SEARCH_M * MAX_SEARCH_M {
+            break;
+        }
+        let road = segment.data;
+        if !usable(road) {
+            continue;
+        }
+        let closest = match segment.geom().closest_point(&Point::from(pt)) {
+            Closest::Intersection(pt) | Closest::SinglePoint(pt) => pt,
+            Closest::Indeterminate => continue,
+        };
+        let connector = Line::new(pt, closest.into());
+        if !crosses_obstacle(obstacles, connector, road) {
+            return Some((road, connector));
+        }
+    }
+    None
+}
+
+fn crosses_obstacle(
+    obstacles: &RTree<GeomWithData<Line, Option<RoadID>>>,
+    connector: Line,
+    road: RoadID,
+) -> bool {
+    // The road might meet a severance right where the connector reaches it
+    let length = Euclidean.length(&connector);
+    if length < 1.0 {
+        return false;
+    }
+    let connector = Line::new(
+        connector.start,
+        connector.end - connector.delta() * (0.5 / length),
+    );
+    let bbox = connector.bounding_rect();
+    obstacles
+        .locate_in_envelope_intersecting(&AABB::from_corners(bbox.min().into(), bbox.max().into()))
+        .any(|obstacle| obstacle.data != Some(road) && obstacle.geom().intersects(&connector))
+}
+
+/// Which entrances are on the outline of this polygon, including courtyards? The entrances come
+/// from all the ways in a multipolygon, which might form several polygons.
+pub fn entrances_on_polygon(polygon: &Polygon, entrances: &[Coord]) -> Vec<Coord> {
+    entrances
+        .iter()
+        .filter(|pt| {
+            std::iter::once(polygon.exterior())
+                .chain(polygon.interiors())
+                .any(|ring| ring.coords().any(|c| c == *pt))
+        })
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use geo::{Densify, Euclidean, Line, LineString, Point};
+use graph::{Graph, IntersectionID};
+use rstar::RTree;
+use rstar::primitives::GeomWithData;
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::RoadKind;
+use crate::traffic::{parse_lanes, speed_limit_kmph};
+
+/// How close a road has to be to a footway to count as the traffic alongside it, in meters
+const ADJACENT_M: f64 = 15.0;
+/// How often to check for traffic alongside a footway, in meters
+const SAMPLE_SPACING_M: f64 = 10.0;
+
+const ROUGH_SURFACES: [&str; 12] = [
+    "unpaved",
+    "gravel",
+    "fine_gravel",
+    "dirt",
+    "earth",
+    "ground",
+    "grass",
+    "mud",
+    "sand",
+    "pebblestone",
+    "cobblestone",
+    "unhewn_cobblestone",
+];
+const BAD_SMOOTHNESS: [&str; 5] = ["bad", "very_bad", "horrible", "very_horrible", "impassable"];
+
+/// How pleasant a road is to walk along
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Comfort {
+    /// From 0 (very unpleasant) to 1
+    pub score: f64,
+    /// Level of traffic stress from the traffic on, next to, or across the road, from 1 (none or
+    /// calm) to 4
+    pub traffic_stress: usize,
+    /// Why the score isn't 1
+    pub reasons: Vec<Reason>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Reason {
+    TrafficStress,
+    SharedWithTraffic,
+    Narrow,
+    Unlit,
+    RoughSurface,
+    BadSmoothness,
+}
+
+impl Comfort {
+    /// With no traffic nearby and nothing known about the surface
+    pub fn calm() -> Self {
+        Self {
+            score: 1.0,
+            traffic_stress: 1,
+            reasons: Vec::new(),
+        }
+    }
+
+    fn penalize(&mut self, amount: f64, reason: Reason) {
+        self.score -= amount;
+        self.reasons.push(reason);
+    }
+
+    pub fn describe_reasons(&self) -> Vec<String> {
+        self.reasons
+            .iter()
+            .map(|reason| match reason {
+                Reason::TrafficStress => format!("level {} traffic stress", self.traffic_stress),
+                Reason::SharedWithTraffic => "shared with traffic".to_string(),
+                Reason::Narrow => "narrow".to_string(),
+                Reason::Unlit => "unlit".to_string(),
+                Reason::RoughSurface => "rough surface".to_string(),
+                Reason::BadSmoothness => "bad smoothness".to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Level of traffic stress from a road's speed, and its type and lanes as a proxy for volume
+pub fn traffic_stress(tags: &Tags, country: Option<&str>) -> usize {
+    let speed = speed_limit_kmph(tags, country).unwrap_or(50.0);
+    let mut stress = if speed <= 20.0 {
+        1
+    } else if speed <= 30.0 {
+        2
+    } else if speed <= 50.0 {
+        3
+    } else {
+        4
+    };
+
+    // Busier roads
+    let highway = tags.get("highway").map(|x| x.as_str()).unwrap_or("");
+    let highway = highway.strip_suffix("_link").unwrap_or(highway);
+    let lanes = tags.get("lanes").and_then(|x| parse_lanes(x)).unwrap_or(2);
+    if matches!(highway, "motorway" | "trunk" | "primary") || lanes >= 4 {
+        stress += 1;
+    } else if matches!(highway, "living_street" | "pedestrian" | "service") {
+        stress = stress.saturating_sub(1);
+    }
+    stress.clamp(1, 4)
+}
+
+/// What `score_roads` needs to know about one road
+struct ScoredRoad<'a> {
+    linestring: &'a LineString,
+    tags: &'a Tags,
+    kind: RoadKind,
+    ends: [IntersectionID; 2],
+}
+
+/// Scores every road. Footways take their traffic stress from the road with traffic alongside
+/// most of their length, within `ADJACENT_M`, or if there isn't one and they were split from a
+/// road, the speed limit copied from it. Crossings take it from the roads they cross. Roads shared
+/// with traffic are less comfortable still.
+pub fn score_roads(graph: &Graph, road_kinds: &[RoadKind], country: Option<&str>) -> Vec<Comfort> {
+    let roads: Vec<ScoredRoad> = graph
+        .roads
+        .iter()
+        .map(|r| ScoredRoad {
+            linestring: &r.linestring,
+            tags: &r.osm_tags,
+            kind: road_kinds[r.id.0],
+            ends: [r.src_i, r.dst_i],
+        })
+        .collect();
+    score(&roads, country)
+}
+
+fn score(roads: &[ScoredRoad], country: Option<&str>) -> Vec<Comfort> {
+    let with_traffic: RTree<GeomWithData<Line, usize>> = RTree::bulk_load(
+        roads
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| has_traffic(r.kind))
+            .flat_map(|(idx, r)| {
+                r.linestring
+                    .lines()
+                    .map(move |line| GeomWithData::new(line, idx))
+            })
+            .collect(),
+    );
+    let mut traffic_at: HashMap<IntersectionID, Vec<usize>> = HashMap::new();
+    for (idx, r) in roads.iter().enumerate() {
+        if has_traffic(r.kind) {
+            for i in r.ends {
+                traffic_at.entry(i).or_default().push(idx);
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for road in roads {
+        let tags = road.tags;
+        let mut comfort = Comfort::calm();
+        let stress = match road.kind {
+            RoadKind::Severance | RoadKind::WithTraffic => Some(traffic_stress(tags, country)),
+            RoadKind::Footway => match alongside(road.linestring, &with_traffic) {
+                Some(idx) => Some(traffic_stress(roads[idx].tags, country)),
+                None if tags.has("maxspeed") => Some(traffic_stress(tags, country)),
+                None => None,
+            },
+            // At-grade crossings share a node with the roads they cross
+            RoadKind::Crossing(_) => road
+                .ends
+                .iter()
+                .flat_map(|i| traffic_at.get(i).into_iter().flatten())
+                .map(|idx| traffic_stress(roads[*idx].tags, country))
+                .max(),
+            _ => None,
+        };
+        if let Some(stress) = stress {
+            comfort.traffic_stress = stress;
+            if stress > 1 {
+                comfort.penalize(0.15 * (stress - 1) as f64, Reason::TrafficStress);
+            }
+        }
+        if road.kind == RoadKind::WithTraffic {
+            comfort.penalize(0.15, Reason::SharedWithTraffic);
+        }
+
+        // Roads with traffic, and sidewalks split from them, describe the carriageway with the
+        // usual tags. Use the sidewalk's own where they're tagged.
+        let carriageway = road.kind == RoadKind::WithTraffic || split_from_road(tags);
+        let width = if carriageway {
+            sidewalk_tags(tags, "width")
+                .into_iter()
+                .filter_map(|x| parse_width(x))
+                .reduce(f64::min)
+        } else {
+            tags.get("width").and_then(|x| parse_width(x))
+        };
+        if width.is_some_and(|width| width < 1.5) {
+            comfort.penalize(0.1, Reason::Narrow);
+        }
+        if tags.is("lit", "no") {
+            comfort.penalize(0.05, Reason::Unlit);
+        }
+        let surface_tag = |key: &str| {
+            if carriageway {
+                // Without a sidewalk, people walk on the carriageway
+                sidewalk_tags(tags, key)
+                    .first()
+                    .copied()
+                    .or_else(|| tags.get(key))
+            } else {
+                tags.get(key)
+            }
+        };
+        if surface_tag("surface").is_some_and(|x| ROUGH_SURFACES.contains(&x.as_str())) {
+            comfort.penalize(0.15, Reason::RoughSurface);
+        }
+        if surface_tag("smoothness").is_some_and(|x| BAD_SMOOTHNESS.contains(&x.as_str())) {
+            comfort.penalize(0.1, Reason::BadSmoothness);
+        }
+
+        comfort.score = comfort.score.max(0.0);
+        results.push(comfort);
+    }
+    results
+}
+
+fn has_traffic(kind: RoadKind) -> bool {
+    matches!(kind, RoadKind::Severance | RoadKind::WithTraffic)
+}
+
+/// The road with traffic closest to most of a footway, checking every `SAMPLE_SPACING_M`. Only
+/// roads within `ADJACENT_M` count.
+fn alongside(
+    linestring: &LineString,
+    with_traffic: &RTree<GeomWithData<Line, usize>>,
+) -> Option<usize> {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for pt in Euclidean.densify(linestring, SAMPLE_SPACING_M).0 {
+        let nearest = with_traffic
+            .nearest_neighbor_iter_with_distance_2(&Point::from(pt))
+            .next()
+            .filter(|(_, dist2)| *dist2 <= ADJACENT_M * ADJACENT_M);
+        if let Some((obj, _)) = nearest {
+            *counts.entry(obj.data).or_default() += 1;
+        }
+    }
+    // Break ties by the lower index, to be deterministic
+    counts
+        .into_iter()
+        .max_by_key(|(idx, count)| (*count, std::cmp::Reverse(*idx)))
+        .map(|(idx, _)| idx)
+}
+
+/// Sidewalks split from a road keep its tags, including the `sidewalk` tags
+fn split_from_road(tags: &Tags) -> bool {
+    tags.is("footway", "sidewalk")
+        && [
+            "sidewalk",
+            "sidewalk:both",
+            "sidewalk:left",
+            "sidewalk:right",
+        ]
+        .iter()
+        .any(|key| tags.has(key))
+}
+
+/// Values of `sidewalk:*:{key}` for every side
+fn sidewalk_tags<'a>(tags: &'a Tags, key: &str) -> Vec<&'a String> {
+    [
+        "sidewalk:both",
+        "sidewalk:left",
+        "sidewalk:right",
+        "sidewalk",
+    ]
+    .into_iter()
+    .filter_map(|side| tags.get(&format!("{side}:{key}")))
+    .collect()
+}
+
+/// Parses values like "2", "1.5 m", or "80 cm" into meters
+fn parse_width(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(cm) = value.strip_suffix("cm") {
+        return cm.trim().parse::<f64>().ok().map(|x| x / 100.0);
+    }
+    value.trim_end_matches('m').trim().parse::<f64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_traffic_stress() {
+        assert_eq!(
+            traffic_stress(
+                &tags(vec!["highway=living_street", "maxspeed=20"]),
+                Some("DE")
+            ),
+            1
+        );
+        assert_eq!(
+            traffic_stress(
+                &tags(vec!["highway=residential", "maxspeed=30"]),
+                Some("DE")
+            ),
+            2
+        );
+        assert_eq!(
+            traffic_stress(&tags(vec!["highway=primary", "maxspeed=50"]), Some("DE")),
+            4
+        );
+
+        assert_eq!(parse_width("1.5 m"), Some(1.5));
+        assert_eq!(parse_width("80 cm"), Some(0.8));
+        assert_eq!(parse_width("narrow"), None);
+    }
+
+    #[test]
+    fn test_score_roads() {
+        let ls = |pts: Vec<(f64, f64)>| LineString::from(pts);
+        let i = IntersectionID;
+
+        // A primary road heading east, and a quiet residential road heading north from it
+        let primary_ls = ls(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let primary_tags = tags(vec!["highway=primary", "maxspeed=50"]);
+        let residential_ls = ls(vec![(100.0, 0.0), (100.0, -100.0)]);
+        let residential_tags = tags(vec![
+            "highway=residential",
+            "maxspeed=30",
+            "width=5",
+            "sidewalk:both:width=1",
+        ]);
+        // An L-shaped footway, mostly along the residential road. Its bounding box's center is
+        // too far from both roads.
+        let footway_ls = ls(vec![(50.0, -5.0), (95.0, -5.0), (95.0, -100.0)]);
+        let footway_tags = tags(vec!["highway=footway"]);
+        // A crossing over the primary road
+        let crossing_ls = ls(vec![(50.0, 10.0), (50.0, 0.0), (50.0, -10.0)]);
+        let crossing_tags = tags(vec!["highway=footway", "footway=crossing"]);
+
+        let roads = vec![
+            ScoredRoad {
+                linestring: &primary_ls,
+                tags: &primary_tags,
+                kind: RoadKind::Severance,
+                ends: [i(0), i(1)],
+            },
+            ScoredRoad {
+                linestring: &residential_ls,
+                tags: &residential_tags,
+                kind: RoadKind::WithTraffic,
+                ends: [i(1), i(2)],
+            },
+            ScoredRoad {
+                linestring: &footway_ls,
+                tags: &footway_tags,
+                kind: RoadKind::Footway,
+                ends: [i(3), i(4)],
+            },
+            ScoredRoad {
+                linestring: &crossing_ls,
+                tags: &crossing_tags,
+                kind: RoadKind::Crossing(crate::CrossingKind::Unmarked),
+                ends: [i(5), i(0)],
+            },
+        ];
+        let results = score(&roads, Some("DE"));
+
+        assert_eq!(results[0].traffic_stress, 4);
+        // The carriageway is wide, but the sidewalks aren't
+        assert_eq!(
+            results[1].reasons,
+            vec![
+                Reason::TrafficStress,
+                Reason::SharedWithTraffic,
+                Reason::Narrow
+            ]
+        );
+        assert_eq!(results[2].traffic_stress, 2);
+        assert_eq!(results[3].traffic_stress, 4);
+        assert_eq!(
+            results[3].describe_reasons(),
+            vec!["level 4 traffic stress".to_string()]
+        );
+    }
+}
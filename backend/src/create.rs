@@ -1,74 +1,209 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
 use std::time::Duration;
 
 use anyhow::Result;
-use geo::{Coord, LineString, Polygon};
+use geo::{BoundingRect, Contains, Coord, LineString, Point, Polygon};
 use graph::{Direction, Graph, RoadID, Timer};
-use osm_reader::{NodeID, OsmID, RelationID, WayID};
+use osm_reader::{Element, NodeID, OsmID, RelationID, WayID};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{AABB, RTree};
 use utils::Tags;
-use utils::osm2graph::OsmReader;
+use utils::osm2graph::{EdgeID, OsmReader};
 
-use crate::{Crossing, CrossingKind, MapModel, Profile, RoadKind, Settings, cost};
+use crate::areas::{connect_pedestrian_areas, is_pedestrian_area};
+use crate::barriers::barrier_access;
+use crate::buildings::{ScrapedBuilding, assign_buildings, entrances_on_polygon};
+use crate::levels::conveying_direction;
+use crate::multipolygon::build_multipolygon;
+use crate::qa::{Issue, IssueKind, conflicting_crossing_tags};
+use crate::signals::SignalTiming;
+use crate::{
+    Access, Crossing, CrossingKind, CrossingShare, MapModel, OpeningHours, Profile, RoadKind,
+    Settings, Slope, StepFree, routing_cost,
+};
 
 impl MapModel {
     pub fn create(input_bytes: &[u8], profile: Profile) -> Result<Self> {
-        let mut extra_scraped = CrossingsAndBuildings::default();
-        let graph = Graph::new(
+        let post_process_input = Rc::new(RefCell::new(PostProcessInput::default()));
+        let mut extra_scraped = CrossingsAndBuildings {
+            multipolygon_members: multipolygon_members(input_bytes)?,
+            post_process_input: post_process_input.clone(),
+            ..Default::default()
+        };
+        let mut graph = Graph::new(
             input_bytes,
             &mut extra_scraped,
-            post_process_graph(profile),
-            scrape_graph(profile),
+            post_process_graph(profile.clone(), post_process_input.clone()),
+            scrape_graph(profile.clone()),
             vec![
-                ("walking".to_string(), walking_profile(profile)),
-                ("cross_anywhere".to_string(), cross_anywhere(profile)),
+                ("walking".to_string(), walking_profile(profile.clone())),
+                (
+                    "cross_anywhere".to_string(),
+                    cross_anywhere(profile.clone()),
+                ),
             ],
             &mut Timer::new("build graph", None),
         )?;
 
-        let road_kinds = graph
+        let road_kinds: Vec<RoadKind> = graph
             .roads
             .iter()
             .map(|r| profile.classify(&r.osm_tags).unwrap())
             .collect();
-        let gradients = std::iter::repeat(0.0).take(graph.roads.len()).collect();
+        let mut access: Vec<Access> = graph
+            .roads
+            .iter()
+            .map(|r| profile.access(&r.osm_tags))
+            .collect();
+        let step_free: Vec<StepFree> = graph
+            .roads
+            .iter()
+            .map(|r| {
+                let from_way = StepFree::from_way_tags(&r.osm_tags);
+                match extra_scraped.step_free_per_road.remove(&r.id) {
+                    Some(from_nodes) => from_way.worst(from_nodes),
+                    None => from_way,
+                }
+            })
+            .collect();
+        // Buildings shouldn't be assigned to roads on the other side of a fence or wall
+        let blocking_lines: Vec<LineString> = extra_scraped
+            .barrier_lines
+            .iter()
+            .filter(|(_, _, _, tags, _)| barrier_access(&profile, tags) == Some(Access::Excluded))
+            .map(|(_, _, linestring, _, _)| {
+                let mut linestring = linestring.clone();
+                graph.mercator.to_mercator_in_place(&mut linestring);
+                linestring
+            })
+            .collect();
+        let barriers = crate::barriers::collect_barriers(
+            &graph,
+            &profile,
+            extra_scraped.barrier_nodes,
+            extra_scraped.barrier_lines,
+        );
+        let changed = crate::barriers::restrict_roads(&mut access, &barriers);
+        let slopes = std::iter::repeat(Slope::default())
+            .take(graph.roads.len())
+            .collect();
+        let comfort = crate::comfort::score_roads(&graph, &road_kinds, profile.country.as_deref());
+
+        // Issues from post-processing are already in Mercator
+        let mut issues = std::mem::take(&mut post_process_input.borrow_mut().issues);
+        for mut issue in extra_scraped.issues {
+            graph.mercator.to_mercator_in_place(&mut issue.geometry);
+            issues.push(issue);
+        }
 
-        let crossings = extra_scraped
+        let mut crossings: Vec<Crossing> = extra_scraped
             .crossings
             .into_iter()
             .map(|(osm_id, pt, tags, roads)| Crossing {
-                kind: CrossingKind::from_tags(&tags),
-                osm_id,
+                kind: CrossingKind::from_tags(&tags, profile.country.as_deref()),
+                url: osm_id.to_string(),
+                node: Some(osm_id.0),
                 point: graph.mercator.pt_to_mercator(pt),
                 roads,
+                timing: SignalTiming::from_tags(&tags),
+                island: tags.is("crossing:island", "yes"),
                 tags,
+                cluster: 0,
+                stages: 1,
             })
             .collect();
+        crossings.extend(crate::grade_separation::find_grade_separated_crossings(
+            &graph,
+            &road_kinds,
+        ));
+        for crossing in &crossings {
+            if let Some(problem) = conflicting_crossing_tags(&crossing.tags) {
+                issues.push(Issue::new(
+                    IssueKind::ConflictingCrossingTags,
+                    crossing.url.clone(),
+                    Point::from(crossing.point),
+                    problem,
+                ));
+            }
+        }
+        issues.extend(crate::qa::check_graph(&graph, &road_kinds));
+        if !issues.is_empty() {
+            info!("Found {} possible tagging problems", issues.len());
+        }
 
-        // TODO Careful with this; do we need two closest roads for the two profiles?
-        let profile = graph.profile_names["cross_anywhere"];
-        let mut buildings_per_road = HashMap::new();
-        for mut b in extra_scraped.buildings {
-            graph.mercator.to_mercator_in_place(&mut b);
-            // TODO Use the centroid?
-            let r = graph
-                .snap_to_road(*b.exterior().coords().next().unwrap(), profile)
-                .road;
-            buildings_per_road.entry(r).or_insert_with(Vec::new).push(b);
+        let crossing_shares =
+            crate::crossings::cluster_crossings(&graph, &road_kinds, &mut crossings);
+        remove_excluded_roads(&mut graph, changed, &access);
+
+        let buildings =
+            assign_buildings(&graph, &road_kinds, blocking_lines, extra_scraped.buildings);
+
+        // Opening hours can come from barriers along a road (matched while scraping), the road
+        // itself, or an area like a park that it's inside
+        let mut opening_hours = extra_scraped.opening_hours_per_road;
+        for road in &graph.roads {
+            if let Some(raw) = road.osm_tags.get("opening_hours") {
+                match OpeningHours::parse(raw) {
+                    Some(oh) => opening_hours.entry(road.id).or_default().push(oh),
+                    None => warn!("Ignoring unparseable opening_hours={raw} on {}", road.way),
+                }
+            }
+        }
+        let road_bboxes = RTree::bulk_load(
+            graph
+                .roads
+                .iter()
+                .filter_map(|r| {
+                    let bbox = r.linestring.bounding_rect()?;
+                    Some(GeomWithData::new(
+                        Rectangle::from_corners(bbox.min().into(), bbox.max().into()),
+                        r.id,
+                    ))
+                })
+                .collect(),
+        );
+        for (mut polygon, oh) in extra_scraped.timed_areas {
+            graph.mercator.to_mercator_in_place(&mut polygon);
+            let Some(bbox) = polygon.bounding_rect() else {
+                continue;
+            };
+            for obj in road_bboxes
+                .locate_in_envelope(&AABB::from_corners(bbox.min().into(), bbox.max().into()))
+            {
+                if polygon.contains(&graph.roads[obj.data.0].linestring) {
+                    opening_hours.entry(obj.data).or_default().push(oh.clone());
+                }
+            }
         }
 
         Ok(Self {
             graph,
             road_kinds,
+            access,
+            step_free,
+            crossing_shares,
             crossings,
-            gradients,
+            barriers,
+            slopes,
+            comfort,
 
-            buildings_per_road,
+            buildings: buildings.buildings,
+            buildings_per_road: buildings.buildings_per_road,
+            building_problems: buildings.problems,
+            opening_hours,
+            issues,
 
-            walking_settings: Settings::uk(),
+            walking_settings: Settings::for_country(profile.country.as_deref()),
             cross_anywhere_settings: Settings {
                 obey_crossings: false,
-                ..Settings::uk()
+                ..Settings::for_country(profile.country.as_deref())
             },
+            country: profile.country,
+            walking_closed: BTreeSet::new(),
+            cross_anywhere_closed: BTreeSet::new(),
+            closed_at: None,
         })
     }
 }
@@ -76,67 +211,328 @@ impl MapModel {
 #[derive(Default)]
 struct CrossingsAndBuildings {
     crossings: Vec<(NodeID, Coord, Tags, HashSet<RoadID>)>,
-    buildings: Vec<Polygon>,
+    /// Ways that might be crossings, with their nodes
+    crossing_ways: Vec<(Tags, Vec<(NodeID, Coord)>)>,
+    buildings: Vec<ScrapedBuilding>,
+    /// `entrance=*` nodes
+    entrances: HashMap<NodeID, Coord>,
+    /// Entrances on every way that has any, for multipolygon buildings
+    way_entrances: HashMap<WayID, Vec<Coord>>,
+    barrier_nodes: Vec<(NodeID, Coord, Tags, HashSet<RoadID>)>,
+    /// Fences, walls, and so on, with the roads sharing a node with them
+    barrier_lines: Vec<(WayID, Vec<NodeID>, LineString, Tags, HashSet<RoadID>)>,
+    /// Gates and other barriers that're only open sometimes
+    timed_barriers: Vec<(NodeID, OpeningHours)>,
+    /// Parks and similar areas that're only open sometimes
+    timed_areas: Vec<(Polygon, OpeningHours)>,
+    opening_hours_per_road: HashMap<RoadID, Vec<OpeningHours>>,
+    /// Kerbs, stiles, and other nodes that aren't step-free, and whether they're kerbs
+    step_free_nodes: Vec<(NodeID, StepFree, bool)>,
+    step_free_per_road: HashMap<RoadID, StepFree>,
+    /// Ways in multipolygons that're needed, found before scraping
+    multipolygon_members: HashSet<WayID>,
+    /// Those ways, for assembling multipolygons
+    way_geometries: HashMap<WayID, Vec<Coord>>,
+    post_process_input: Rc<RefCell<PostProcessInput>>,
+    /// In WGS84
+    issues: Vec<Issue>,
+}
+
+/// Things scraped from OSM that `post_process_graph` needs. It runs before the scraper is handed
+/// back, so this is shared.
+#[derive(Default)]
+struct PostProcessInput {
+    /// In WGS84
+    /// With the way or a member way of the relation
+    pedestrian_areas: Vec<(WayID, Tags, Polygon)>,
+    /// `highway=elevator` nodes
+    elevators: HashMap<NodeID, Tags>,
+    /// Handed back from `post_process_graph`, in Mercator
+    issues: Vec<Issue>,
 }
 
 impl OsmReader for CrossingsAndBuildings {
     fn node(&mut self, id: NodeID, pt: Coord, tags: Tags) {
+        if tags.is("highway", "elevator") {
+            self.post_process_input
+                .borrow_mut()
+                .elevators
+                .insert(id, tags.clone());
+        }
+        if tags.has("entrance") && !tags.is("entrance", "no") {
+            self.entrances.insert(id, pt);
+        }
+        let (step_free, is_kerb) = StepFree::from_node_tags(&tags);
+        if step_free != StepFree::Usable {
+            self.step_free_nodes.push((id, step_free, is_kerb));
+        }
+
+        if tags.has("barrier") {
+            if let Some(raw) = tags.get("opening_hours") {
+                match OpeningHours::parse(raw) {
+                    Some(oh) => self.timed_barriers.push((id, oh)),
+                    None => warn!("Ignoring unparseable opening_hours={raw} on {id:?}"),
+                }
+            }
+            self.barrier_nodes
+                .push((id, pt, tags.clone(), HashSet::new()));
+        }
+
         if tags.is("highway", "crossing")
             || (tags.is("highway", "traffic_signals") && tags.is("crossing", "traffic_signals"))
         {
             self.crossings.push((id, pt, tags, HashSet::new()));
         } else if tags.has("crossing") {
             warn!("Ignoring possible crossing {id:?} with tags {tags:?}");
+            self.issues.push(Issue::new(
+                IssueKind::UnrecognisedCrossingNode,
+                id.to_string(),
+                Point::from(pt),
+                format!(
+                    "crossing={} without highway=crossing",
+                    tags.get("crossing").unwrap()
+                ),
+            ));
         }
     }
 
     fn way(
         &mut self,
-        _: WayID,
+        id: WayID,
         node_ids: &Vec<NodeID>,
         node_mapping: &HashMap<NodeID, Coord>,
         tags: &Tags,
     ) {
+        let entrances: Vec<Coord> = node_ids
+            .iter()
+            .filter_map(|id| self.entrances.get(id).cloned())
+            .collect();
+        if !entrances.is_empty() {
+            self.way_entrances.insert(id, entrances.clone());
+        }
+
         if tags.has("building") {
-            // TODO Handle relations, and refactor this
             // geo closes the polygon for us
-            self.buildings.push(Polygon::new(
-                LineString::new(node_ids.into_iter().map(|id| node_mapping[id]).collect()),
-                Vec::new(),
+            self.buildings.push(ScrapedBuilding {
+                polygon: Polygon::new(
+                    LineString::new(node_ids.into_iter().map(|id| node_mapping[id]).collect()),
+                    Vec::new(),
+                ),
+                entrances,
+            });
+        } else if tags.is_any("leisure", vec!["park", "garden"])
+            && node_ids.len() >= 4
+            && node_ids[0] == *node_ids.last().unwrap()
+        {
+            if let Some(raw) = tags.get("opening_hours") {
+                match OpeningHours::parse(raw) {
+                    Some(oh) => self.timed_areas.push((
+                        Polygon::new(
+                            LineString::new(node_ids.iter().map(|id| node_mapping[id]).collect()),
+                            Vec::new(),
+                        ),
+                        oh,
+                    )),
+                    None => warn!("Ignoring unparseable opening_hours={raw} on {id}"),
+                }
+            }
+        }
+
+        let pts: Vec<Coord> = node_ids.iter().map(|id| node_mapping[id]).collect();
+        if is_pedestrian_area(tags) && pts.len() >= 4 && pts[0] == pts[pts.len() - 1] {
+            self.post_process_input.borrow_mut().pedestrian_areas.push((
+                id,
+                tags.clone(),
+                Polygon::new(LineString::new(pts.clone()), Vec::new()),
             ));
         }
+        if tags.has("crossing")
+            || ["footway", "cycleway", "path"]
+                .iter()
+                .any(|k| tags.is(k, "crossing"))
+        {
+            self.crossing_ways.push((
+                tags.clone(),
+                node_ids.iter().map(|id| (*id, node_mapping[id])).collect(),
+            ));
+        }
+        if self.multipolygon_members.contains(&id) {
+            self.way_geometries.insert(id, pts);
+        }
+
+        if tags.has("barrier") && !tags.has("highway") {
+            self.barrier_lines.push((
+                id,
+                node_ids.clone(),
+                LineString::new(node_ids.iter().map(|id| node_mapping[id]).collect()),
+                tags.clone(),
+                HashSet::new(),
+            ));
+        }
+    }
+
+    fn relation(&mut self, _: RelationID, members: &Vec<(String, OsmID)>, tags: &Tags) {
+        if !tags.is("type", "multipolygon") {
+            return;
+        }
+        if is_pedestrian_area(tags) {
+            // Connections across the area link to its first outer way, since they need a way
+            let outer = members.iter().find_map(|(role, member)| match member {
+                OsmID::Way(way) if role != "inner" => Some(*way),
+                _ => None,
+            });
+            if let Some(outer) = outer {
+                for polygon in self.multipolygon(members) {
+                    self.post_process_input.borrow_mut().pedestrian_areas.push((
+                        outer,
+                        tags.clone(),
+                        polygon,
+                    ));
+                }
+            }
+        }
+        if tags.has("building") {
+            let entrances: Vec<Coord> = members
+                .iter()
+                .filter_map(|(_, member)| match member {
+                    OsmID::Way(way) => self.way_entrances.get(way),
+                    _ => None,
+                })
+                .flatten()
+                .cloned()
+                .collect();
+            for polygon in self.multipolygon(members) {
+                let entrances = entrances_on_polygon(&polygon, &entrances);
+                self.buildings.push(ScrapedBuilding { polygon, entrances });
+            }
+        }
     }
+}
 
-    fn relation(&mut self, _: RelationID, _: &Vec<(String, OsmID)>, _: &Tags) {}
+/// Multipolygons come after their member ways, so find the members of the ones needed upfront,
+/// rather than keep every way's geometry
+fn multipolygon_members(input_bytes: &[u8]) -> Result<HashSet<WayID>> {
+    let mut ways = HashSet::new();
+    osm_reader::parse(input_bytes, |elem| {
+        let Element::Relation { tags, members, .. } = elem else {
+            return;
+        };
+        let tags: Tags = tags.into();
+        if tags.is("type", "multipolygon") && (tags.has("building") || is_pedestrian_area(&tags)) {
+            for (_, member) in members.iter() {
+                if let OsmID::Way(way) = member {
+                    ways.insert(*way);
+                }
+            }
+        }
+    })?;
+    Ok(ways)
+}
+
+impl CrossingsAndBuildings {
+    fn multipolygon(&self, members: &Vec<(String, OsmID)>) -> Vec<Polygon> {
+        let mut outers = Vec::new();
+        let mut inners = Vec::new();
+        for (role, member) in members {
+            let OsmID::Way(way) = member else {
+                continue;
+            };
+            // The area might be clipped
+            let Some(pts) = self.way_geometries.get(way) else {
+                continue;
+            };
+            if role == "inner" {
+                inners.push(pts.clone());
+            } else {
+                outers.push(pts.clone());
+            }
+        }
+        build_multipolygon(outers, inners)
+    }
+}
+
+/// The routing profiles only see a road's own tags. Remove roads a barrier excludes entirely, so
+/// they can't be snapped to. Any other change to a road's cost is picked up while searching.
+fn remove_excluded_roads(graph: &mut Graph, changed: BTreeSet<RoadID>, access: &[Access]) {
+    for r in changed {
+        if access[r.0] == Access::Excluded {
+            graph.roads[r.0].access.fill(Direction::None);
+        }
+    }
+}
+
+/// Fills in crossing details missing from a crossing node from its crossing way. Returns where
+/// they disagree, as (key, the node's value, the way's value).
+fn merge_crossing_tags(node_tags: &mut Tags, way_tags: &Tags) -> Vec<(String, String, String)> {
+    let mut conflicts = Vec::new();
+    for (k, v) in &way_tags.0 {
+        if !(k.starts_with("crossing") || k == "button_operated") {
+            continue;
+        }
+        match node_tags.get(k) {
+            Some(existing) if existing != v => {
+                conflicts.push((k.clone(), existing.clone(), v.clone()));
+            }
+            Some(_) => {}
+            None => {
+                node_tags.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    conflicts
 }
 
 fn walking_profile(profile: Profile) -> Box<dyn Fn(&Tags, &LineString) -> (Direction, Duration)> {
+    let settings = Settings::for_country(profile.country.as_deref());
     Box::new(move |tags, linestring| {
         let exclude = (Direction::None, Duration::ZERO);
         let kind = profile.classify(tags);
-        if kind == None || kind == Some(RoadKind::Severance) {
+        let access = profile.access(tags);
+        if kind == None || kind == Some(RoadKind::Severance) || access == Access::Excluded {
             return exclude;
         }
 
         // TODO We haven't calculated it yet
-        let gradient = 0.0;
-        let (cost1, cost2) = cost(linestring, kind.unwrap(), gradient, &Settings::uk());
-        (Direction::Both, cost1 + cost2)
+        let slope = Slope::default();
+        let cost = routing_cost(
+            linestring,
+            kind.unwrap(),
+            slope,
+            CrossingShare::default(),
+            access,
+            &StepFree::from_way_tags(tags),
+            // Comfort needs the surrounding roads, and it's ignored by default
+            1.0,
+            &settings,
+        );
+        (conveying_direction(tags), cost)
     })
 }
 
 fn cross_anywhere(profile: Profile) -> Box<dyn Fn(&Tags, &LineString) -> (Direction, Duration)> {
+    let settings = Settings::for_country(profile.country.as_deref());
     Box::new(move |tags, linestring| {
         let exclude = (Direction::None, Duration::ZERO);
         let kind = profile.classify(tags);
-        if kind == None {
+        let access = profile.access(tags);
+        if kind == None || access == Access::Excluded {
             return exclude;
         }
 
         // TODO We haven't calculated it yet
-        let gradient = 0.0;
-        let (cost1, cost2) = cost(linestring, kind.unwrap(), gradient, &Settings::uk());
-        (Direction::Both, cost1 + cost2)
+        let slope = Slope::default();
+        let cost = routing_cost(
+            linestring,
+            kind.unwrap(),
+            slope,
+            CrossingShare::default(),
+            access,
+            &StepFree::from_way_tags(tags),
+            // Comfort needs the surrounding roads, and it's ignored by default
+            1.0,
+            &settings,
+        );
+        (conveying_direction(tags), cost)
     })
 }
 
@@ -165,80 +561,253 @@ fn scrape_graph(
                 keep_crossings.push(crossing);
             }
         }
-        extra_scraped.crossings = keep_crossings;
-        Ok(())
-    })
-}
 
-fn post_process_graph(profile: Profile) -> Box<dyn Fn(&mut utils::osm2graph::Graph) -> Result<()>> {
-    Box::new(move |graph| {
-        // Look for intersections with only Severances and WithTraffic streets. Assume footways
-        // directly connecting to severances (on both sides) are mistakenly tagged crossings.
-        let mut disconnect: Vec<(utils::osm2graph::IntersectionID, utils::osm2graph::EdgeID)> =
-            Vec::new();
-
-        'INTERSECTION: for intersection in graph.intersections.values() {
-            let mut severances = Vec::new();
-            let mut with_traffics = Vec::new();
-            for e in &intersection.edges {
-                let kind = profile.classify(&graph.edges[e].osm_tags);
-                match kind {
-                    Some(RoadKind::Severance) => {
-                        severances.push(*e);
-                    }
-                    Some(RoadKind::WithTraffic) => {
-                        with_traffics.push(*e);
-                    }
-                    Some(RoadKind::Footway | RoadKind::Crossing(_)) => {
-                        // TODO What if there are mixes involving WithTraffic too?
-                        continue 'INTERSECTION;
+        // Crossings are often only tagged on the crossing way, with an untagged node where it meets
+        // the severance. When both are tagged, fill in details missing from the node.
+        let mut crossing_nodes: HashMap<NodeID, usize> = keep_crossings
+            .iter()
+            .enumerate()
+            .map(|(idx, crossing)| (crossing.0, idx))
+            .collect();
+        let mut num_from_ways = 0;
+        for (tags, nodes) in std::mem::take(&mut extra_scraped.crossing_ways) {
+            if !matches!(profile.classify(&tags), Some(RoadKind::Crossing(_))) {
+                continue;
+            }
+            for (node, pt) in nodes {
+                let Some(roads) = severance_nodes.get(&node) else {
+                    continue;
+                };
+                if let Some(idx) = crossing_nodes.get(&node) {
+                    let node_tags = &mut keep_crossings[*idx].2;
+                    for (k, existing, v) in merge_crossing_tags(node_tags, &tags) {
+                        extra_scraped.issues.push(Issue::new(
+                            IssueKind::ConflictingCrossingTags,
+                            node.to_string(),
+                            Point::from(pt),
+                            format!("The node has {k}={existing}, but the way has {k}={v}"),
+                        ));
                     }
-                    None => {}
+                } else {
+                    // Only the crossing details; the rest of the way's tags describe the footway
+                    let mut node_tags = Tags::empty();
+                    node_tags.insert("highway", "crossing");
+                    merge_crossing_tags(&mut node_tags, &tags);
+                    crossing_nodes.insert(node, keep_crossings.len());
+                    keep_crossings.push((node, pt, node_tags, roads.clone()));
+                    extra_scraped.issues.push(Issue::new(
+                        IssueKind::CrossingWayWithoutNode,
+                        node.to_string(),
+                        Point::from(pt),
+                        "A crossing way meets a severance here, but the node isn't tagged"
+                            .to_string(),
+                    ));
+                    num_from_ways += 1;
                 }
             }
-            // TODO Check that we actually have to cross the severance, and that it's not just
-            // connected on one side
-            if !severances.is_empty() && !with_traffics.is_empty() {
-                for e in with_traffics {
-                    disconnect.push((intersection.id, e));
+        }
+        if num_from_ways > 0 {
+            info!("Found {num_from_ways} crossings only tagged on crossing ways");
+        }
+        extra_scraped.crossings = keep_crossings;
+
+        let barriers: HashMap<NodeID, &OpeningHours> = extra_scraped
+            .timed_barriers
+            .iter()
+            .map(|(node, oh)| (*node, oh))
+            .collect();
+        let mut opening_hours_per_road: HashMap<RoadID, Vec<OpeningHours>> = HashMap::new();
+        for edge in graph.edges.values() {
+            for node in &edge.node_ids {
+                if let Some(oh) = barriers.get(node) {
+                    opening_hours_per_road
+                        .entry(RoadID(edge.id.0))
+                        .or_default()
+                        .push((*oh).clone());
                 }
             }
         }
+        extra_scraped.opening_hours_per_road = opening_hours_per_road;
 
-        // Don't allow connections between Severances and WithTraffics. This will apply for
-        // routing, isochrones, network disconnections, etc. Achieve this by duplicating the
-        // Intersection.
-        for (original_node, edge_id) in disconnect {
-            graph
-                .intersections
-                .get_mut(&original_node)
-                .unwrap()
-                .edges
-                .retain(|e| *e != edge_id);
-
-            let new_intersection_id = new_intersection_id(graph);
-            assert!(!graph.intersections.contains_key(&new_intersection_id));
-            let mut intersection_copy = graph.intersections[&original_node].clone();
-            intersection_copy.edges = vec![edge_id];
-            // TODO Keep same osm_node?
-            intersection_copy.id = new_intersection_id;
-            graph
-                .intersections
-                .insert(new_intersection_id, intersection_copy);
+        // Kerbs only affect the edge they're in the middle of, or a crossing they're at the end
+        // of. A raised kerb at the end of a crossing doesn't stop somebody using the sidewalk.
+        let step_free_nodes: HashMap<NodeID, (&StepFree, bool)> = extra_scraped
+            .step_free_nodes
+            .iter()
+            .map(|(node, step_free, is_kerb)| (*node, (step_free, *is_kerb)))
+            .collect();
+        let mut step_free_per_road: HashMap<RoadID, StepFree> = HashMap::new();
+        for edge in graph.edges.values() {
+            let is_crossing = matches!(
+                profile.classify(&edge.osm_tags),
+                Some(RoadKind::Crossing(_))
+            );
+            for (idx, node) in edge.node_ids.iter().enumerate() {
+                let Some((step_free, is_kerb)) = step_free_nodes.get(node) else {
+                    continue;
+                };
+                let at_end = idx == 0 || idx == edge.node_ids.len() - 1;
+                if *is_kerb && at_end && !is_crossing {
+                    continue;
+                }
+                let r = RoadID(edge.id.0);
+                let worst = match step_free_per_road.remove(&r) {
+                    Some(x) => x.worst((*step_free).clone()),
+                    None => (*step_free).clone(),
+                };
+                step_free_per_road.insert(r, worst);
+            }
+        }
+        extra_scraped.step_free_per_road = step_free_per_road;
 
-            let edge = graph.edges.get_mut(&edge_id).unwrap();
-            if edge.src == original_node {
-                edge.src = new_intersection_id;
+        // Match barriers to the roads touching them, and whether the node is inside the road
+        let mut roads_per_node: HashMap<NodeID, Vec<(EdgeID, bool)>> = HashMap::new();
+        for edge in graph.edges.values() {
+            if profile.classify(&edge.osm_tags).is_none() {
+                continue;
+            }
+            for (idx, node) in edge.node_ids.iter().enumerate() {
+                let interior = idx != 0 && idx != edge.node_ids.len() - 1;
+                roads_per_node
+                    .entry(*node)
+                    .or_default()
+                    .push((edge.id, interior));
+            }
+        }
+        extra_scraped.barrier_nodes.retain_mut(|barrier| {
+            if let Some(edges) = roads_per_node.get(&barrier.0) {
+                let touching: Vec<_> = edges
+                    .iter()
+                    .map(|(e, interior)| {
+                        let edge = &graph.edges[e];
+                        (RoadID(e.0), edge.osm_way, &edge.osm_tags, *interior)
+                    })
+                    .collect();
+                barrier.3.extend(crate::barriers::controlled_roads(
+                    &profile, &barrier.2, &touching,
+                ));
             }
-            if edge.dst == original_node {
-                edge.dst = new_intersection_id;
+            !barrier.3.is_empty()
+        });
+        for barrier in &mut extra_scraped.barrier_lines {
+            for node in &barrier.1 {
+                if let Some(edges) = roads_per_node.get(node) {
+                    barrier.4.extend(edges.iter().map(|(e, _)| RoadID(e.0)));
+                }
             }
         }
+        Ok(())
+    })
+}
+
+fn post_process_graph(
+    profile: Profile,
+    shared: Rc<RefCell<PostProcessInput>>,
+) -> Box<dyn Fn(&mut utils::osm2graph::Graph) -> Result<()>> {
+    Box::new(move |graph| {
+        if profile.split_sidewalks {
+            crate::sidewalks::split_sidewalks(graph, &profile);
+        }
+        let input = std::mem::take(&mut *shared.borrow_mut());
+        // After splitting sidewalks, so the final intersections get split and connected
+        crate::levels::split_levels(graph, &input.elevators);
+        connect_pedestrian_areas(graph, &profile, input.pedestrian_areas);
+        crate::grade_separation::mark_grade_separated_crossings(graph, &profile);
+
+        shared.borrow_mut().issues = crate::side_streets::disconnect_side_streets(graph, &profile);
 
         Ok(())
     })
 }
 
-fn new_intersection_id(graph: &utils::osm2graph::Graph) -> utils::osm2graph::IntersectionID {
+pub(crate) fn new_intersection_id(
+    graph: &utils::osm2graph::Graph,
+) -> utils::osm2graph::IntersectionID {
     utils::osm2graph::IntersectionID(graph.intersections.keys().max().unwrap().0 + 1)
 }
+
+/// Adds a new intersection with the same OSM node as an existing one, but no edges yet
+pub(crate) fn copy_intersection(
+    graph: &mut utils::osm2graph::Graph,
+    i: utils::osm2graph::IntersectionID,
+    pt: Coord,
+) -> utils::osm2graph::IntersectionID {
+    let id = new_intersection_id(graph);
+    let mut copy = graph.intersections[&i].clone();
+    copy.id = id;
+    copy.edges = Vec::new();
+    copy.point = pt.into();
+    graph.intersections.insert(id, copy);
+    id
+}
+
+/// Adds a new edge, belonging to an existing OSM way
+pub(crate) fn add_edge(
+    graph: &mut utils::osm2graph::Graph,
+    osm_way: WayID,
+    src: utils::osm2graph::IntersectionID,
+    dst: utils::osm2graph::IntersectionID,
+    linestring: LineString,
+    osm_tags: Tags,
+) {
+    let id = utils::osm2graph::EdgeID(graph.edges.keys().max().unwrap().0 + 1);
+    graph.edges.insert(
+        id,
+        utils::osm2graph::Edge {
+            id,
+            src,
+            dst,
+            osm_way,
+            osm_tags,
+            // New edges don't pass through any OSM nodes
+            node_ids: Vec::new(),
+            linestring,
+        },
+    );
+    graph.intersections.get_mut(&src).unwrap().edges.push(id);
+    graph.intersections.get_mut(&dst).unwrap().edges.push(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_merge_crossing_tags() {
+        let way = tags(vec![
+            "highway=footway",
+            "footway=crossing",
+            "crossing=traffic_signals",
+            "button_operated=yes",
+            "surface=asphalt",
+        ]);
+
+        // Only tagged on the way
+        let mut node = tags(vec!["highway=crossing"]);
+        assert!(merge_crossing_tags(&mut node, &way).is_empty());
+        assert!(node.is("highway", "crossing"));
+        assert!(node.is("crossing", "traffic_signals"));
+        assert!(node.is("button_operated", "yes"));
+        assert!(!node.has("footway"));
+        assert!(!node.has("surface"));
+
+        // Tagged on both, filling in what's missing from the node
+        let mut node = tags(vec!["highway=crossing", "crossing=traffic_signals"]);
+        assert!(merge_crossing_tags(&mut node, &way).is_empty());
+        assert!(node.is("button_operated", "yes"));
+        assert!(!node.has("surface"));
+
+        // Tagged on both, disagreeing
+        let mut node = tags(vec!["highway=crossing", "crossing=uncontrolled"]);
+        assert_eq!(
+            merge_crossing_tags(&mut node, &way),
+            vec![(
+                "crossing".to_string(),
+                "uncontrolled".to_string(),
+                "traffic_signals".to_string()
+            )]
+        );
+        assert!(node.is("crossing", "uncontrolled"));
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use geo::{Coord, Distance, Euclidean, Line, LineString, Point};
+use graph::{Graph, RoadID};
+use rstar::RTree;
+use rstar::primitives::GeomWithData;
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::signals::SignalTiming;
+use crate::{Crossing, RoadKind, grade_separation, signals};
+
+/// Crossings over parallel carriageways closer than this are one logical crossing
+const CLUSTER_DISTANCE_M: f64 = 30.0;
+/// Carriageways with bearings differing by less than this are parallel
+const PARALLEL_DEGREES: f64 = 30.0;
+/// A crossing way ending this close to a crossing node is part of it
+const ATTACHED_M: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CrossingKind {
+    /// Traffic signals for pedestrians, without more detail
+    Signalized,
+    /// UK signals with a flashing green man phase
+    Pelican,
+    /// UK signals that detect people waiting and still crossing
+    Puffin,
+    /// UK signals shared with people cycling
+    Toucan,
+    /// Marked, and drivers have to give way
+    Zebra,
+    /// Marked, but drivers don't have to give way, like dashed lines in some countries
+    Marked,
+    /// No markings or signals, but an island in the middle, so people only wait for a gap in one
+    /// direction at a time
+    Refuge,
+    /// An official place to cross, maybe with dropped kerbs, but nothing stops traffic
+    Unmarked,
+    /// Somewhere people cross, but that isn't designed for it
+    Informal,
+    /// Not enough detail is tagged
+    Other,
+    /// A footway passing over a severance, usually with steps or ramps at both ends
+    Footbridge,
+    /// A footway passing under a severance
+    Underpass,
+}
+
+/// How crossings are tagged and work in one country
+pub struct CountryRules {
+    /// Does a marked crossing without zebra stripes still mean drivers have to give way?
+    pub marked_is_zebra: bool,
+    /// Pelican, puffin, and toucan crossings are only used in the UK and Ireland
+    pub uk_signals: bool,
+}
+
+impl CountryRules {
+    /// Rules for an ISO 3166-1 alpha-2 country code
+    pub fn new(country: Option<&str>) -> Self {
+        match country {
+            // Mappers have long used crossing=uncontrolled for zebras here
+            Some("GB" | "IE") => Self {
+                marked_is_zebra: true,
+                uk_signals: true,
+            },
+            // Any marked crosswalk gives people right of way
+            Some("US" | "CA") => Self {
+                marked_is_zebra: true,
+                uk_signals: false,
+            },
+            _ => Self {
+                marked_is_zebra: false,
+                uk_signals: false,
+            },
+        }
+    }
+}
+
+/// Seconds usually spent waiting at each kind of crossing in a country. Grade-separated crossings
+/// count the climb up and down instead. These are rough guesses, meant to be adjusted in
+/// `Settings`.
+pub fn default_delays(country: Option<&str>) -> BTreeMap<CrossingKind, f64> {
+    use CrossingKind::*;
+    let mut delays = BTreeMap::from([
+        (Signalized, 30.0),
+        (Pelican, 30.0),
+        (Puffin, 25.0),
+        (Toucan, 30.0),
+        (Zebra, 0.0),
+        (Marked, 5.0),
+        (Refuge, 10.0),
+        (Unmarked, 15.0),
+        (Informal, 20.0),
+        (Other, 10.0),
+        (Footbridge, 60.0),
+        (Underpass, 40.0),
+    ]);
+    match country {
+        // Drivers often don't yield at crosswalks, and signal cycles are long
+        Some("US" | "CA") => {
+            delays.insert(Zebra, 5.0);
+            delays.insert(Signalized, 45.0);
+            delays.insert(Unmarked, 20.0);
+        }
+        Some("DE" | "AT" | "CH") => {
+            delays.insert(Signalized, 40.0);
+        }
+        _ => {}
+    }
+    delays
+}
+
+impl CrossingKind {
+    /// Classifies a crossing node or way using the rules for a country
+    pub fn from_tags(tags: &Tags, country: Option<&str>) -> Self {
+        let rules = CountryRules::new(country);
+        let crossing = tags.get("crossing").map(|x| x.as_str());
+        let crossing_ref = tags.get("crossing_ref").map(|x| x.as_str());
+        let markings = tags.get("crossing:markings").map(|x| x.as_str());
+
+        let signalized = crossing == Some("traffic_signals")
+            || tags.is("crossing:signals", "yes")
+            || tags.is("highway", "traffic_signals");
+        if signalized {
+            if rules.uk_signals {
+                match crossing_ref {
+                    Some("pelican") => return Self::Pelican,
+                    Some("puffin") => return Self::Puffin,
+                    Some("toucan") => return Self::Toucan,
+                    _ => {}
+                }
+                // Signals that only serve people are almost always button-operated here. Puffins
+                // have replaced pelicans as the default.
+                if tags.is("button_operated", "yes") {
+                    if tags.is_any("bicycle", vec!["yes", "designated"]) {
+                        return Self::Toucan;
+                    }
+                    return Self::Puffin;
+                }
+            }
+            return Self::Signalized;
+        }
+
+        if crossing == Some("informal") {
+            return Self::Informal;
+        }
+        if matches!(crossing_ref, Some("zebra" | "tiger"))
+            || crossing == Some("zebra")
+            || matches!(
+                markings,
+                Some("zebra" | "ladder" | "ladder:skewed" | "ladder:paired" | "zebra:double")
+            )
+        {
+            return Self::Zebra;
+        }
+        let marked = match markings {
+            Some("no") => false,
+            Some(_) => true,
+            None => matches!(crossing, Some("marked" | "uncontrolled")),
+        };
+        if marked {
+            // Only zebra-like markings were handled above
+            return if rules.marked_is_zebra && matches!(markings, None | Some("yes")) {
+                Self::Zebra
+            } else {
+                Self::Marked
+            };
+        }
+        // Only unmarked crossings become a different kind with an island. Elsewhere it's kept on
+        // the Crossing, since it can split a signalized crossing into stages.
+        if crossing == Some("unmarked") || markings == Some("no") {
+            return if tags.is("crossing:island", "yes") {
+                Self::Refuge
+            } else {
+                Self::Unmarked
+            };
+        }
+
+        // At-grade crossings sometimes sit on a road bridge
+        if crossing.is_none() {
+            if grade_separation::is_raised(tags) {
+                return Self::Footbridge;
+            }
+            if grade_separation::is_sunken(tags) {
+                return Self::Underpass;
+            }
+        }
+        Self::Other
+    }
+}
+
+/// How a crossing road's delay relates to the logical crossing it belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrossingShare {
+    /// How many carriageways the logical crossing has, each with its own wait
+    pub stages: usize,
+    /// How many crossing roads make up the logical crossing. Crossing ways are usually split
+    /// where they meet each carriageway, and the delay is spread between all the pieces.
+    pub roads: usize,
+    /// For signalized crossings, the timing tagged or overridden for any stage
+    pub timing: Option<SignalTiming>,
+}
+
+impl Default for CrossingShare {
+    fn default() -> Self {
+        Self {
+            stages: 1,
+            roads: 1,
+            timing: None,
+        }
+    }
+}
+
+impl CrossingShare {
+    /// This road's part of the total delay. The first stage costs the full delay, and later stages
+    /// cost `extra_stage_factor` of it, since the signals are often coordinated.
+    pub fn delay(&self, delay: f64, extra_stage_factor: f64) -> f64 {
+        delay * (1.0 + (self.stages - 1) as f64 * extra_stage_factor) / self.roads as f64
+    }
+}
+
+// A crossing over a dual carriageway with a central island is mapped as one crossing node per
+// carriageway. Group crossings close to each other and side by side over different, parallel
+// severances into one logical crossing. Each crossing gets a cluster ID and a stage count, and
+// each crossing road attached to a crossing node gets its share of the logical crossing's delay.
+pub fn cluster_crossings(
+    graph: &Graph,
+    road_kinds: &[RoadKind],
+    crossings: &mut [Crossing],
+) -> Vec<CrossingShare> {
+    let cluster = find_clusters(crossings, |r| {
+        let road = &graph.roads[r.0];
+        (&road.linestring, &road.way)
+    });
+
+    // Renumber clusters from 0
+    let mut ids: BTreeMap<usize, usize> = BTreeMap::new();
+    for c in &cluster {
+        let next = ids.len();
+        ids.entry(*c).or_insert(next);
+    }
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (idx, c) in cluster.iter().enumerate() {
+        crossings[idx].cluster = ids[c];
+        members.entry(ids[c]).or_default().push(idx);
+    }
+    let num_multi_stage = members.values().filter(|m| m.len() > 1).count();
+    if num_multi_stage > 0 {
+        info!("Found {num_multi_stage} crossings with more than one stage");
+    }
+
+    // Find the crossing roads attached to each crossing node
+    let crossing_roads = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .filter(|r| matches!(road_kinds[r.id.0], RoadKind::Crossing(_)))
+            .flat_map(|r| {
+                [r.linestring.0[0], *r.linestring.0.last().unwrap()]
+                    .map(|pt| GeomWithData::new([pt.x, pt.y], r.id))
+            })
+            .collect(),
+    );
+    let mut shares = vec![CrossingShare::default(); graph.roads.len()];
+    for cluster_members in members.values() {
+        if !at_grade(&crossings[cluster_members[0]]) {
+            continue;
+        }
+        let roads: BTreeSet<RoadID> = cluster_members
+            .iter()
+            .flat_map(|idx| {
+                let pt = crossings[*idx].point;
+                crossing_roads
+                    .locate_within_distance([pt.x, pt.y], ATTACHED_M * ATTACHED_M)
+                    .map(|obj| obj.data)
+            })
+            .collect();
+        let stages = count_stages(cluster_members.iter().map(|idx| &crossings[*idx]));
+        for idx in cluster_members {
+            crossings[*idx].stages = stages;
+        }
+        let timing = cluster_members
+            .iter()
+            .find_map(|idx| crossings[*idx].timing);
+        for r in &roads {
+            shares[r.0] = CrossingShare {
+                stages,
+                roads: roads.len(),
+                timing,
+            };
+        }
+    }
+    shares
+}
+
+/// How many separate waits a logical crossing has. Each carriageway is one stage, and a signalized
+/// crossing with a central island is usually staggered, with its own phase for each half. An
+/// island elsewhere doesn't add a wait, and an unmarked crossing with one is already a Refuge.
+fn count_stages<'a>(members: impl Iterator<Item = &'a Crossing>) -> usize {
+    let mut stages = 0;
+    let mut staggered = false;
+    for c in members {
+        stages += 1;
+        staggered |= c.island && signals::is_signalized(c.kind);
+    }
+    if staggered { stages.max(2) } else { stages }
+}
+
+/// Footbridges and underpasses are already one crossing, however many carriageways they span
+fn at_grade(c: &Crossing) -> bool {
+    !matches!(c.kind, CrossingKind::Footbridge | CrossingKind::Underpass)
+}
+
+/// Groups at-grade crossings into logical crossings, returning a representative for each crossing.
+/// Footbridges and underpasses are left alone. `carriageway` gives the geometry and OSM way of a
+/// severance road.
+fn find_clusters<'a, W: PartialEq + 'a>(
+    crossings: &[Crossing],
+    carriageway: impl Fn(RoadID) -> (&'a LineString, &'a W),
+) -> Vec<usize> {
+    let rtree = RTree::bulk_load(
+        crossings
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| at_grade(c))
+            .map(|(idx, c)| GeomWithData::new([c.point.x, c.point.y], idx))
+            .collect(),
+    );
+    // Union-find would be faster, but clusters are small
+    let mut cluster: Vec<usize> = (0..crossings.len()).collect();
+    for (idx1, c1) in crossings.iter().enumerate() {
+        if !at_grade(c1) {
+            continue;
+        }
+        for obj in rtree.locate_within_distance(
+            [c1.point.x, c1.point.y],
+            CLUSTER_DISTANCE_M * CLUSTER_DISTANCE_M,
+        ) {
+            let idx2 = obj.data;
+            let c2 = &crossings[idx2];
+            if idx2 <= idx1
+                || cluster[idx1] == cluster[idx2]
+                || !c1.roads.is_disjoint(&c2.roads)
+                || !c1.roads.iter().any(|r1| {
+                    c2.roads.iter().any(|r2| {
+                        side_by_side(c1.point, carriageway(*r1), c2.point, carriageway(*r2))
+                    })
+                })
+            {
+                continue;
+            }
+            let (keep, replace) = (cluster[idx1], cluster[idx2]);
+            for c in &mut cluster {
+                if *c == replace {
+                    *c = keep;
+                }
+            }
+        }
+    }
+    cluster
+}
+
+/// Are two crossings over the carriageways of one dual carriageway? The carriageways have to be
+/// parallel, separate ways, and beside each other. Otherwise the crossings on two arms of an
+/// ordinary crossroads, where a street is split at the junction, would look the same.
+fn side_by_side<W: PartialEq>(
+    pt1: Coord,
+    (linestring1, way1): (&LineString, &W),
+    pt2: Coord,
+    (linestring2, way2): (&LineString, &W),
+) -> bool {
+    if way1 == way2 {
+        return false;
+    }
+    let bearing = bearing_near(linestring1, pt1);
+    if !parallel(bearing, bearing_near(linestring2, pt2)) {
+        return false;
+    }
+    let offset = pt2 - pt1;
+    let offset_bearing = offset.y.atan2(offset.x).to_degrees().rem_euclid(180.0);
+    // Perpendicular to the carriageway
+    parallel(offset_bearing, bearing + 90.0)
+}
+
+/// The bearing in degrees of the part of a road closest to a point, ignoring direction
+fn bearing_near(linestring: &LineString, pt: Coord) -> f64 {
+    let pt = Point::from(pt);
+    let line = linestring
+        .lines()
+        .min_by(|a, b| {
+            Euclidean
+                .distance(a, &pt)
+                .total_cmp(&Euclidean.distance(b, &pt))
+        })
+        .unwrap_or_else(|| Line::new(pt, pt));
+    let delta = line.delta();
+    delta.y.atan2(delta.x).to_degrees().rem_euclid(180.0)
+}
+
+fn parallel(bearing1: f64, bearing2: f64) -> bool {
+    let diff = (bearing1 - bearing2).rem_euclid(180.0);
+    diff.min(180.0 - diff) <= PARALLEL_DEGREES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_crossing_kind() {
+        for (country, input, expected) in [
+            (
+                "GB",
+                vec!["crossing=traffic_signals"],
+                CrossingKind::Signalized,
+            ),
+            (
+                "GB",
+                vec!["crossing=traffic_signals", "crossing_ref=pelican"],
+                CrossingKind::Pelican,
+            ),
+            (
+                "GB",
+                vec!["crossing=traffic_signals", "button_operated=yes"],
+                CrossingKind::Puffin,
+            ),
+            (
+                "DE",
+                vec!["crossing=traffic_signals", "button_operated=yes"],
+                CrossingKind::Signalized,
+            ),
+            ("GB", vec!["crossing=uncontrolled"], CrossingKind::Zebra),
+            ("DE", vec!["crossing=uncontrolled"], CrossingKind::Marked),
+            (
+                "DE",
+                vec!["crossing=marked", "crossing:markings=zebra"],
+                CrossingKind::Zebra,
+            ),
+            (
+                "GB",
+                vec!["crossing=uncontrolled", "crossing:markings=dots"],
+                CrossingKind::Marked,
+            ),
+            ("US", vec!["crossing=unmarked"], CrossingKind::Unmarked),
+            (
+                "GB",
+                vec!["crossing=unmarked", "crossing:island=yes"],
+                CrossingKind::Refuge,
+            ),
+            ("GB", vec!["crossing=informal"], CrossingKind::Informal),
+            ("GB", vec!["highway=crossing"], CrossingKind::Other),
+        ] {
+            assert_eq!(
+                CrossingKind::from_tags(&tags(input.clone()), Some(country)),
+                expected,
+                "{country} {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_crossing_share() {
+        let single = CrossingShare {
+            stages: 1,
+            roads: 2,
+            timing: None,
+        };
+        assert_eq!(single.delay(30.0, 0.5), 15.0);
+        let dual = CrossingShare {
+            stages: 2,
+            roads: 4,
+            timing: None,
+        };
+        assert_eq!(dual.delay(30.0, 0.5), 11.25);
+        assert!(parallel(5.0, 175.0));
+        assert!(!parallel(0.0, 90.0));
+        assert!(parallel(0.0, 270.0));
+    }
+
+    #[test]
+    fn test_count_stages() {
+        let crossing = |kind: CrossingKind, island: bool| Crossing {
+            url: String::new(),
+            node: None,
+            point: Coord { x: 0.0, y: 0.0 },
+            roads: std::collections::HashSet::new(),
+            tags: Tags::empty(),
+            kind,
+            island,
+            cluster: 0,
+            stages: 1,
+            timing: None,
+        };
+        let count = |crossings: Vec<Crossing>| count_stages(crossings.iter());
+
+        assert_eq!(count(vec![crossing(CrossingKind::Signalized, false)]), 1);
+        // A staggered crossing waits once on each side of the island
+        assert_eq!(count(vec![crossing(CrossingKind::Puffin, true)]), 2);
+        assert_eq!(count(vec![crossing(CrossingKind::Zebra, true)]), 1);
+        // Over a dual carriageway, the island is already counted
+        assert_eq!(
+            count(vec![
+                crossing(CrossingKind::Signalized, true),
+                crossing(CrossingKind::Signalized, true)
+            ]),
+            2
+        );
+    }
+
+    #[test]
+    fn test_find_clusters() {
+        let crossing_kind = |kind: CrossingKind, x: f64, y: f64, road: usize| Crossing {
+            url: String::new(),
+            node: None,
+            point: Coord { x, y },
+            roads: [RoadID(road)].into_iter().collect(),
+            tags: Tags::empty(),
+            kind,
+            island: false,
+            cluster: 0,
+            stages: 1,
+            timing: None,
+        };
+        let crossing =
+            |x: f64, y: f64, road: usize| crossing_kind(CrossingKind::Signalized, x, y, road);
+        let clusters = |crossings: &[Crossing], roads: &[(LineString, i64)]| {
+            find_clusters(crossings, |r| (&roads[r.0].0, &roads[r.0].1))
+        };
+        let east_west = |x1: f64, x2: f64, y: f64| LineString::from(vec![(x1, y), (x2, y)]);
+
+        // A dual carriageway, with two oneway ways 10m apart
+        let dual = vec![
+            (east_west(0.0, 100.0, 0.0), 1),
+            (east_west(100.0, 0.0, 10.0), 2),
+        ];
+        assert_eq!(
+            clusters(&[crossing(50.0, 0.0, 0), crossing(51.0, 10.0, 1)], &dual),
+            vec![0, 0]
+        );
+        // A footbridge over one carriageway, beside the crossing over both
+        assert_eq!(
+            clusters(
+                &[
+                    crossing_kind(CrossingKind::Footbridge, 48.0, 0.0, 0),
+                    crossing(50.0, 0.0, 0),
+                    crossing(51.0, 10.0, 1)
+                ],
+                &dual
+            ),
+            vec![0, 1, 1]
+        );
+
+        // A street split at a crossroads, with crossings on both arms
+        let split = vec![
+            (east_west(0.0, 50.0, 0.0), 1),
+            (east_west(50.0, 100.0, 0.0), 1),
+        ];
+        let arms = [crossing(40.0, 0.0, 0), crossing(60.0, 0.0, 1)];
+        assert_eq!(clusters(&arms, &split), vec![0, 1]);
+        // Even if the street changes name there
+        let renamed = vec![
+            (east_west(0.0, 50.0, 0.0), 1),
+            (east_west(50.0, 100.0, 0.0), 2),
+        ];
+        assert_eq!(clusters(&arms, &renamed), vec![0, 1]);
+    }
+}
@@ -4,12 +4,12 @@ use geojson::FeatureCollection;
 use graph::{IntersectionID, RoadID};
 use petgraph::graphmap::UnGraphMap;
 
-use crate::{MapModel, RoadKind};
+use crate::{Access, MapModel, RoadKind};
 
 pub fn find_connected_components(map: &MapModel) -> FeatureCollection {
     let mut graph: UnGraphMap<IntersectionID, RoadID> = UnGraphMap::new();
     for r in &map.graph.roads {
-        if map.road_kinds[r.id.0] != RoadKind::Severance {
+        if walkable(map, r.id) {
             graph.add_edge(r.src_i, r.dst_i, r.id);
         }
     }
@@ -50,6 +50,10 @@ fn nodes_to_edges(map: &MapModel, nodes: Vec<IntersectionID>) -> BTreeSet<RoadID
     for i in nodes {
         edges.extend(map.graph.intersections[i.0].roads.clone());
     }
-    edges.retain(|r| map.road_kinds[r.0] != RoadKind::Severance);
+    edges.retain(|r| walkable(map, *r));
     edges
 }
+
+fn walkable(map: &MapModel, r: RoadID) -> bool {
+    map.road_kinds[r.0] != RoadKind::Severance && map.access[r.0] != Access::Excluded
+}
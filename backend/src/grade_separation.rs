@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+
+use geo::line_intersection::line_intersection;
+use geo::{BoundingRect, Coord, Intersects, LineIntersection, LineString};
+use graph::Graph;
+use utils::Tags;
+
+use crate::{Crossing, CrossingKind, Profile, RoadKind};
+
+/// Does a footway go over a road?
+pub fn is_raised(tags: &Tags) -> bool {
+    (tags.has("bridge") && !tags.is("bridge", "no")) || layer(tags) > 0
+}
+
+/// Does a footway go under a road?
+pub fn is_sunken(tags: &Tags) -> bool {
+    (tags.has("tunnel") && !tags.is("tunnel", "no")) || layer(tags) < 0
+}
+
+fn layer(tags: &Tags) -> isize {
+    tags.get("layer")
+        .and_then(|x| x.parse::<isize>().ok())
+        .unwrap_or(0)
+}
+
+// Footbridges and underpasses cross severances without sharing a node, so they look like ordinary
+// footways. Find ones that pass over or under a severance on a different layer, and tag them as
+// crossings, so the profile classifies them that way and CrossingKind::from_tags picks the kind.
+pub fn mark_grade_separated_crossings(graph: &mut utils::osm2graph::Graph, profile: &Profile) {
+    let severances: Vec<(&LineString, isize)> = graph
+        .edges
+        .values()
+        .filter(|e| profile.classify(&e.osm_tags) == Some(RoadKind::Severance))
+        .map(|e| (&e.linestring, layer(&e.osm_tags)))
+        .collect();
+
+    let mut mark = Vec::new();
+    for edge in graph.edges.values() {
+        if !edge
+            .osm_tags
+            .is_any("highway", vec!["footway", "path", "steps", "cycleway"])
+            || !(is_raised(&edge.osm_tags) || is_sunken(&edge.osm_tags))
+            || profile.classify(&edge.osm_tags) != Some(RoadKind::Footway)
+        {
+            continue;
+        }
+        let footway_layer = layer(&edge.osm_tags);
+        if severances.iter().any(|(linestring, severance_layer)| {
+            // A bridge without a layer tag is still above a road without one
+            (footway_layer != *severance_layer || footway_layer == 0)
+                && proper_crossing(&edge.linestring, linestring).is_some()
+        }) {
+            mark.push(edge.id);
+        }
+    }
+    if mark.is_empty() {
+        return;
+    }
+    info!(
+        "Found {} footbridges or underpasses over severances",
+        mark.len()
+    );
+    for e in mark {
+        graph
+            .edges
+            .get_mut(&e)
+            .unwrap()
+            .osm_tags
+            .insert("footway", "crossing");
+    }
+}
+
+/// Finds where each footbridge or underpass crosses severances
+pub fn find_grade_separated_crossings(graph: &Graph, road_kinds: &[RoadKind]) -> Vec<Crossing> {
+    let severances: Vec<_> = graph
+        .roads
+        .iter()
+        .filter(|r| road_kinds[r.id.0] == RoadKind::Severance)
+        .collect();
+
+    let mut crossings = Vec::new();
+    for road in &graph.roads {
+        let RoadKind::Crossing(kind @ (CrossingKind::Footbridge | CrossingKind::Underpass)) =
+            road_kinds[road.id.0]
+        else {
+            continue;
+        };
+        let Some(bbox) = road.linestring.bounding_rect() else {
+            continue;
+        };
+
+        let mut roads = HashSet::new();
+        let mut point = None;
+        for severance in &severances {
+            if !severance
+                .linestring
+                .bounding_rect()
+                .is_some_and(|other| other.intersects(&bbox))
+            {
+                continue;
+            }
+            if let Some(pt) = proper_crossing(&road.linestring, &severance.linestring) {
+                roads.insert(severance.id);
+                point.get_or_insert(pt);
+            }
+        }
+        if let Some(point) = point {
+            crossings.push(Crossing {
+                url: road.way.to_string(),
+                node: None,
+                point,
+                roads,
+                tags: road.osm_tags.clone(),
+                kind,
+                island: false,
+                cluster: 0,
+                stages: 1,
+                timing: None,
+            });
+        }
+    }
+    crossings
+}
+
+/// Where two lines first pass through each other. Touching, overlapping, or sharing an endpoint
+/// doesn't count.
+pub fn proper_crossing(ls1: &LineString, ls2: &LineString) -> Option<Coord> {
+    let ends = |ls: &LineString| [ls.0.first().copied(), ls.0.last().copied()];
+    if ends(ls1)
+        .into_iter()
+        .flatten()
+        .any(|pt| ends(ls2).contains(&Some(pt)))
+    {
+        return None;
+    }
+    for line1 in ls1.lines() {
+        for line2 in ls2.lines() {
+            if let Some(LineIntersection::SinglePoint {
+                intersection,
+                is_proper: true,
+            }) = line_intersection(line1, line2)
+            {
+                return Some(intersection);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_crossing_kind() {
+        let kind = |kv: Vec<&str>| CrossingKind::from_tags(&tags(kv), Some("GB"));
+        assert_eq!(
+            kind(vec!["footway=crossing", "bridge=yes"]),
+            CrossingKind::Footbridge
+        );
+        assert_eq!(
+            kind(vec!["footway=crossing", "layer=-1"]),
+            CrossingKind::Underpass
+        );
+        assert_eq!(
+            kind(vec!["footway=crossing", "bridge=no"]),
+            CrossingKind::Other
+        );
+        // An at-grade crossing on a road bridge
+        assert_eq!(
+            kind(vec!["crossing=traffic_signals", "bridge=yes"]),
+            CrossingKind::Signalized
+        );
+        assert_eq!(
+            kind(vec!["crossing=unmarked", "bridge=yes"]),
+            CrossingKind::Unmarked
+        );
+    }
+
+    #[test]
+    fn test_proper_crossing() {
+        let ls = |pts: Vec<(f64, f64)>| LineString::from(pts);
+        let road = ls(vec![(0.0, 0.0), (10.0, 0.0)]);
+        assert_eq!(
+            proper_crossing(&ls(vec![(5.0, -5.0), (5.0, 5.0)]), &road),
+            Some(Coord { x: 5.0, y: 0.0 })
+        );
+        // Ending on the road
+        assert_eq!(
+            proper_crossing(&ls(vec![(5.0, -5.0), (5.0, 0.0)]), &road),
+            None
+        );
+        // Sharing an endpoint
+        assert_eq!(
+            proper_crossing(&ls(vec![(0.0, 0.0), (0.0, 5.0)]), &road),
+            None
+        );
+        // Running alongside
+        assert_eq!(
+            proper_crossing(&ls(vec![(2.0, 0.0), (8.0, 0.0)]), &road),
+            None
+        );
+    }
+}
@@ -1,13 +1,14 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::time::Duration;
 
-use chrono::NaiveTime;
 use geo::{Coord, Densify, Euclidean, Rect};
 use geojson::{Feature, GeoJson};
-use graph::Graph;
+use graph::{Direction, Graph, IntersectionID, Position, ProfileID, RoadID};
 use serde::Deserialize;
 use utils::Grid;
 
-use crate::{MapModel, Settings};
+use crate::{Access, Departure, MapModel, Settings};
 
 impl MapModel {
     pub fn calculate_isochrone(
@@ -17,32 +18,19 @@ impl MapModel {
         time_limit_mins: u64,
         settings1: Settings,
         settings2: Option<Settings>,
+        departure: Departure,
     ) -> GeoJson {
-        let public_transit = false;
-        let start_time = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
         let limit = Duration::from_secs(time_limit_mins * 60);
 
+        let profile1 = self.prepare_profile(settings1, departure);
         let cost_per_road1 = {
-            let profile = self.prepare_profile(settings1);
-            let start = self.graph.snap_to_road(start, profile);
-            self.graph.get_costs(
-                vec![start.intersection],
-                profile,
-                public_transit,
-                start_time,
-                start_time + limit,
-            )
+            let start = self.graph.snap_to_road(start, profile1);
+            self.directed_costs(start, profile1, limit)
         };
         let mut cost_per_road2 = settings2.map(|settings| {
-            let profile = self.prepare_profile(settings);
+            let profile = self.prepare_profile(settings, departure);
             let start = self.graph.snap_to_road(start, profile);
-            self.graph.get_costs(
-                vec![start.intersection],
-                profile,
-                public_transit,
-                start_time,
-                start_time + limit,
-            )
+            self.directed_costs(start, profile, limit)
         });
 
         let mut features = Vec::new();
@@ -67,8 +55,11 @@ impl MapModel {
             Style::Dasymetric => {
                 let empty = Vec::new();
                 for (r, cost1) in cost_per_road1 {
-                    for polygon in self.buildings_per_road.get(&r).unwrap_or(&empty) {
-                        let mut f = self.graph.mercator.to_wgs84_gj(polygon);
+                    for b in self.buildings_per_road[profile1.0]
+                        .get(&r)
+                        .unwrap_or(&empty)
+                    {
+                        let mut f = self.graph.mercator.to_wgs84_gj(&self.buildings[*b]);
                         f.set_property("cost1", cost1.as_secs());
                         if let Some(ref mut costs) = cost_per_road2 {
                             if let Some(cost2) = costs.remove(&r) {
@@ -118,6 +109,91 @@ impl MapModel {
 
         GeoJson::from(features)
     }
+
+    /// The cost to reach the start of every road within a time limit, for a prepared profile.
+    /// This walks each road with the cost in the direction it's walked, so isochrones reach
+    /// further downhill than uphill.
+    pub(crate) fn directed_costs(
+        &self,
+        start: Position,
+        profile: ProfileID,
+        limit: Duration,
+    ) -> BTreeMap<RoadID, Duration> {
+        self.directed_search(start.intersection, profile, limit, None, &[start.road])
+            .cost_per_road
+    }
+
+    /// Searches outwards from an intersection, walking each road with its cost in that direction,
+    /// until the time limit or an optional goal is reached. Passing through roads only meant as a
+    /// destination costs `destination_penalty` once each time, unless they're part of the same way
+    /// as one of the `destinations`, where the route starts or ends.
+    pub(crate) fn directed_search(
+        &self,
+        start: IntersectionID,
+        profile: ProfileID,
+        limit: Duration,
+        goal: Option<IntersectionID>,
+        destinations: &[RoadID],
+    ) -> DirectedSearch {
+        let (settings, closed) = self.prepared(profile);
+        let mut search = DirectedSearch {
+            cost_per_road: BTreeMap::new(),
+            reached_by: HashMap::new(),
+        };
+        let mut visited = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((Duration::ZERO, start, None)));
+
+        while let Some(Reverse((cost, i, step))) = queue.pop() {
+            if !visited.insert(i) {
+                continue;
+            }
+            if let Some(step) = step {
+                search.reached_by.insert(i, step);
+            }
+            if Some(i) == goal {
+                break;
+            }
+            for r in &self.graph.intersections[i.0].roads {
+                let road = &self.graph.roads[r.0];
+                let forwards = road.src_i == i;
+                let allowed = match road.access[profile.0] {
+                    Direction::None => false,
+                    Direction::Forwards => forwards,
+                    Direction::Backwards => !forwards,
+                    Direction::Both => true,
+                };
+                if !allowed {
+                    continue;
+                }
+                search.cost_per_road.entry(*r).or_insert(cost);
+
+                let next = if forwards { road.dst_i } else { road.src_i };
+                let mut next_cost = cost + self.directed_cost(*r, forwards, settings, closed);
+                let entering =
+                    step.is_none_or(|(prev, _)| self.access[prev.0] != Access::Destination);
+                if self.access[r.0] == Access::Destination
+                    && entering
+                    && !destinations
+                        .iter()
+                        .any(|d| self.graph.roads[d.0].way == road.way)
+                {
+                    next_cost += Duration::from_secs_f64(settings.destination_penalty);
+                }
+                if next_cost <= limit && !visited.contains(&next) {
+                    queue.push(Reverse((next_cost, next, Some((*r, forwards)))));
+                }
+            }
+        }
+        search
+    }
+}
+
+pub(crate) struct DirectedSearch {
+    /// The cost to reach the start of every road searched
+    pub cost_per_road: BTreeMap<RoadID, Duration>,
+    /// The road and direction each intersection was first reached by
+    pub reached_by: HashMap<IntersectionID, (RoadID, bool)>,
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap};
+
+use geo::{Coord, LineString};
+use graph::Direction;
+use osm_reader::NodeID;
+use utils::Tags;
+use utils::osm2graph::{EdgeID, Graph, IntersectionID};
+
+use crate::create::{add_edge, copy_intersection};
+
+/// Elevators connect levels at one point. Their edges go out and back along a stub this long, so
+/// there's a real line to snap to and sample along.
+const ELEVATOR_STUB_M: f64 = 1.0;
+
+/// Parses `level=*` into ranges, like "1" => [(1, 1)], "0;2" => [(0, 0), (2, 2)], and "-1-1" =>
+/// [(-1, 1)]. Ways without `level=*` return `None`, meaning they could be on any level.
+pub fn levels(tags: &Tags) -> Option<Vec<(f64, f64)>> {
+    let value = tags.get("level")?;
+    let mut ranges = Vec::new();
+    for part in value.split(';') {
+        let part = part.trim();
+        // Skip a leading minus sign when looking for a range
+        let range = part
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '-')
+            .map(|(idx, _)| (&part[..idx], &part[idx + 1..]));
+        let parsed = match range {
+            Some((from, to)) => from.parse::<f64>().ok().zip(to.parse::<f64>().ok()),
+            None => part.parse::<f64>().ok().map(|x| (x, x)),
+        };
+        if let Some((from, to)) = parsed {
+            ranges.push((from.min(to), from.max(to)));
+        }
+    }
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+/// Could two ways with these levels be connected directly?
+pub fn compatible(levels1: &Option<Vec<(f64, f64)>>, levels2: &Option<Vec<(f64, f64)>>) -> bool {
+    let (Some(levels1), Some(levels2)) = (levels1, levels2) else {
+        return true;
+    };
+    levels1
+        .iter()
+        .any(|(a1, b1)| levels2.iter().any(|(a2, b2)| a1 <= b2 && a2 <= b1))
+}
+
+/// Escalators and moving walkways may only go one way
+pub fn conveying_direction(tags: &Tags) -> Direction {
+    match tags.get("conveying").map(|x| x.as_str()) {
+        Some("forward") => Direction::Forwards,
+        Some("backward") => Direction::Backwards,
+        _ => Direction::Both,
+    }
+}
+
+// OSM ways sharing a node are connected, but indoors, a corridor can pass over another at a
+// different level and share a node by mistake. At every intersection, group the edges that could
+// connect by their `level=*`, and give each group its own intersection. Edges without a level could
+// be on any level, and ways like steps spanning multiple levels join those levels together.
+//
+// Elevators mapped as a node are the exception. The levels served by the elevator are connected
+// through new edges tagged `highway=elevator`, so the wait can be costed.
+pub fn split_levels(graph: &mut Graph, elevators: &HashMap<NodeID, Tags>) {
+    let mut splits: Vec<(IntersectionID, Vec<Vec<EdgeID>>)> = Vec::new();
+    for i in graph.intersections.values() {
+        if i.edges.len() < 2 {
+            continue;
+        }
+        let edge_levels: Vec<_> = i
+            .edges
+            .iter()
+            .map(|e| levels(&graph.edges[e].osm_tags))
+            .collect();
+
+        // Union-find would be faster, but intersections are small
+        let mut group_of: Vec<usize> = (0..i.edges.len()).collect();
+        for a in 0..i.edges.len() {
+            for b in (a + 1)..i.edges.len() {
+                if compatible(&edge_levels[a], &edge_levels[b]) && group_of[a] != group_of[b] {
+                    let (keep, replace) = (group_of[a], group_of[b]);
+                    for g in &mut group_of {
+                        if *g == replace {
+                            *g = keep;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<usize, Vec<EdgeID>> = BTreeMap::new();
+        for (idx, e) in i.edges.iter().enumerate() {
+            groups.entry(group_of[idx]).or_default().push(*e);
+        }
+        if groups.len() > 1 {
+            splits.push((i.id, groups.into_values().collect()));
+        }
+    }
+    if splits.is_empty() {
+        return;
+    }
+    info!("Splitting {} intersections by level", splits.len());
+
+    let mut num_elevators = 0;
+    for (i, groups) in splits {
+        let point = graph.intersections[&i].point;
+        let mut per_group = vec![i];
+        for group in &groups[1..] {
+            let copy = copy_intersection(graph, i, point.into());
+            graph
+                .intersections
+                .get_mut(&i)
+                .unwrap()
+                .edges
+                .retain(|e| !group.contains(e));
+            graph.intersections.get_mut(&copy).unwrap().edges = group.clone();
+            for e in group {
+                let edge = graph.edges.get_mut(e).unwrap();
+                if edge.src == i {
+                    edge.src = copy;
+                }
+                if edge.dst == i {
+                    edge.dst = copy;
+                }
+            }
+            per_group.push(copy);
+        }
+
+        let Some(elevator_tags) = elevators.get(&graph.intersections[&i].osm_node) else {
+            continue;
+        };
+        for a in 0..groups.len() {
+            for b in (a + 1)..groups.len() {
+                let mut tags = elevator_tags.clone();
+                tags.insert("highway", "elevator");
+                tags.insert(
+                    "level",
+                    format!(
+                        "{};{}",
+                        group_level(graph, &groups[a]),
+                        group_level(graph, &groups[b])
+                    ),
+                );
+                let way = graph.edges[&groups[a][0]].osm_way;
+                add_edge(
+                    graph,
+                    way,
+                    per_group[a],
+                    per_group[b],
+                    elevator_linestring(point.into()),
+                    tags,
+                );
+                num_elevators += 1;
+            }
+        }
+    }
+    if num_elevators > 0 {
+        info!("Added {num_elevators} connections through elevators");
+    }
+}
+
+fn elevator_linestring(pt: Coord) -> LineString {
+    LineString::new(vec![
+        pt,
+        Coord {
+            x: pt.x + ELEVATOR_STUB_M,
+            y: pt.y,
+        },
+        pt,
+    ])
+}
+
+/// Describes the level of a group of edges, for tagging elevators
+fn group_level(graph: &Graph, group: &[EdgeID]) -> String {
+    group
+        .iter()
+        .find_map(|e| graph.edges[e].osm_tags.get("level").cloned())
+        .unwrap_or_else(|| "0".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{Euclidean, Length};
+
+    use super::*;
+    use crate::test_utils::tags;
+    use crate::{MapModel, Profile, RoadKind};
+
+    #[test]
+    fn test_levels() {
+        let level = |value: &str| levels(&tags(vec![&format!("level={value}")]));
+        assert_eq!(level("1"), Some(vec![(1.0, 1.0)]));
+        assert_eq!(level("-1"), Some(vec![(-1.0, -1.0)]));
+        assert_eq!(level("0;2"), Some(vec![(0.0, 0.0), (2.0, 2.0)]));
+        assert_eq!(level("-2--1"), Some(vec![(-2.0, -1.0)]));
+        assert_eq!(level("1-3"), Some(vec![(1.0, 3.0)]));
+        assert_eq!(level("roof"), None);
+
+        assert!(compatible(&level("0"), &None));
+        assert!(!compatible(&level("0"), &level("-1")));
+        assert!(compatible(&level("-1;0"), &level("0")));
+        assert!(compatible(&level("1-3"), &level("2")));
+    }
+
+    #[test]
+    fn test_elevator_node() {
+        // Corridors on two levels meeting at an elevator
+        let input = r#"<osm version="0.6">
+            <bounds minlat="51.5" minlon="-0.1" maxlat="51.501" maxlon="-0.098"/>
+            <node id="1" lat="51.5005" lon="-0.1"/>
+            <node id="2" lat="51.5005" lon="-0.099">
+                <tag k="highway" v="elevator"/>
+                <tag k="level" v="0;1"/>
+            </node>
+            <node id="3" lat="51.5005" lon="-0.098"/>
+            <way id="10">
+                <nd ref="1"/>
+                <nd ref="2"/>
+                <tag k="highway" v="corridor"/>
+                <tag k="level" v="0"/>
+            </way>
+            <way id="11">
+                <nd ref="2"/>
+                <nd ref="3"/>
+                <tag k="highway" v="corridor"/>
+                <tag k="level" v="1"/>
+            </way>
+        </osm>"#;
+        let map =
+            MapModel::create(input.as_bytes(), Profile::builtin("SeparateWays").unwrap()).unwrap();
+
+        let elevators: Vec<_> = map
+            .graph
+            .roads
+            .iter()
+            .filter(|r| map.road_kinds[r.id.0] == RoadKind::Elevator)
+            .collect();
+        assert_eq!(elevators.len(), 1);
+        let elevator = elevators[0];
+        assert_ne!(elevator.src_i, elevator.dst_i);
+        // Both ends are at the elevator, with a stub in between to snap onto
+        let ls = &elevator.linestring;
+        assert_eq!(ls.0[0], *ls.0.last().unwrap());
+        assert!(Euclidean.length(ls) > 0.0);
+        // Each level's corridor reaches only one end
+        for r in &map.graph.roads {
+            if map.road_kinds[r.id.0] == RoadKind::Footway {
+                let ends = [r.src_i, r.dst_i];
+                assert!(ends.contains(&elevator.src_i) != ends.contains(&elevator.dst_i));
+            }
+        }
+    }
+}
@@ -1,26 +1,55 @@
 #[macro_use]
 extern crate log;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Once;
 use std::time::Duration;
 
 use geo::{Coord, Euclidean, Length, LineString, Point, Polygon};
 use geojson::GeoJson;
 use graph::{Graph, RoadID};
-use osm_reader::NodeID;
 use serde::{Deserialize, Serialize};
 use utils::Tags;
 use wasm_bindgen::prelude::*;
 
-pub use crate::profiles::Profile;
-
+pub use crate::access::Access;
+pub use crate::crossings::{CrossingKind, CrossingShare};
+pub use crate::opening_hours::{Departure, OpeningHours};
+pub use crate::personas::Persona;
+pub use crate::profiles::{Matcher, Outcome, Profile, Rule};
+pub use crate::recommend::{Recommendation, ZoneRecommendation, recommend_profile};
+pub use crate::slopes::Slope;
+pub use crate::step_free::StepFree;
+pub use crate::traffic::SeveranceThresholds;
+
+mod access;
+mod areas;
+mod barriers;
+mod buildings;
+mod comfort;
 mod create;
+mod crossings;
 mod disconnected;
+mod grade_separation;
 mod isochrone;
+mod levels;
+mod multipolygon;
+mod opening_hours;
+mod personas;
 mod profiles;
+mod qa;
+mod recommend;
 mod route;
 mod scores;
+mod side_streets;
+mod sidewalks;
+mod signals;
+mod slopes;
+mod step_free;
+#[cfg(test)]
+mod test_utils;
+mod time_bands;
+mod traffic;
 
 static START: Once = Once::new();
 
@@ -29,44 +58,66 @@ static START: Once = Once::new();
 pub struct MapModel {
     graph: Graph,
     // Indexed by RoadID
-    gradients: Vec<f64>,
+    slopes: Vec<Slope>,
+    comfort: Vec<comfort::Comfort>,
     road_kinds: Vec<RoadKind>,
+    access: Vec<Access>,
+    step_free: Vec<StepFree>,
+    crossing_shares: Vec<CrossingShare>,
     crossings: Vec<Crossing>,
-
-    buildings_per_road: HashMap<RoadID, Vec<Polygon>>,
-
-    // Do we need to update a router's costs?
+    barriers: Vec<barriers::Barrier>,
+
+    buildings: Vec<Polygon>,
+    /// Indexed by ProfileID, with indices into `buildings`
+    buildings_per_road: Vec<HashMap<RoadID, Vec<usize>>>,
+    building_problems: Vec<buildings::BuildingProblem>,
+    /// Roads that are only open sometimes. Every entry must be open for the road to be usable.
+    opening_hours: HashMap<RoadID, Vec<OpeningHours>>,
+    /// Tagging problems found while building the model, in Mercator
+    issues: Vec<qa::Issue>,
+
+    // The settings and closed roads each profile was last prepared with
     walking_settings: Settings,
     cross_anywhere_settings: Settings,
+    walking_closed: BTreeSet<RoadID>,
+    cross_anywhere_closed: BTreeSet<RoadID>,
+    /// The roads closed at the last departure asked about
+    #[serde(skip)]
+    closed_at: Option<(Departure, BTreeSet<RoadID>)>,
+
+    /// From the profile, for interpreting crossings and default settings
+    country: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Crossing {
-    osm_id: NodeID,
+    /// The crossing node, or the way of a footbridge or underpass
+    url: String,
+    /// The crossing node's ID, for overriding its signal timing
+    node: Option<i64>,
     point: Coord,
     roads: HashSet<RoadID>,
     tags: Tags,
     kind: CrossingKind,
+    /// Tagged with `crossing:island=yes`, so people can wait in the middle
+    island: bool,
+    /// Crossings over parallel carriageways are grouped into one logical crossing
+    cluster: usize,
+    /// How many crossings are in the cluster
+    stages: usize,
+    /// Signal timing from tags or an override. Otherwise, `Settings` has a default.
+    timing: Option<signals::SignalTiming>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-pub enum CrossingKind {
-    Signalized,
-    Zebra,
-    Other,
-}
-
-impl CrossingKind {
-    // TODO UK centric and probably wrong...
-    pub fn from_tags(tags: &Tags) -> Self {
-        if tags.is("crossing", "traffic_signals") {
-            return Self::Signalized;
+impl Crossing {
+    /// How the crossing is described when filtering by kind
+    fn filter_key(&self) -> &str {
+        match (self.tags.get("crossing").map(|x| x.as_str()), self.kind) {
+            (Some(kind), _) => kind,
+            (None, CrossingKind::Footbridge) => "footbridge",
+            (None, CrossingKind::Underpass) => "underpass",
+            (None, _) => "unknown",
         }
-        if tags.is("crossing", "uncontrolled") {
-            // TODO And crossing:markings or crossing_ref?
-            return Self::Zebra;
-        }
-        CrossingKind::Other
     }
 }
 
@@ -83,12 +134,18 @@ pub enum RoadKind {
     /// sidewalks tagged, excluded. Otherwise, may or may not actually have sidewalks. Assumed to
     /// be easy enough to walk along and cross anywhere.
     WithTraffic,
+    /// The outline of a square, plaza, or other open space, or a straight connection across it
+    PedestrianArea,
+    /// Mapped as a way, or added between the levels an elevator node serves
+    Elevator,
+    /// Escalators and moving walkways, which might only go one way
+    Escalator,
 }
 
 #[wasm_bindgen]
 impl MapModel {
-    /// Call either with bytes of an osm.pbf or osm.xml string and a profile name, or a bincoded
-    /// file
+    /// Call either with bytes of an osm.pbf or osm.xml string and a profile (the name of a
+    /// built-in one, or a full set of rules), or a bincoded file
     #[wasm_bindgen(constructor)]
     pub fn new(is_osm: bool, input_bytes: &[u8], profile: JsValue) -> Result<MapModel, JsValue> {
         // Panics shouldn't happen, but if they do, console.log them.
@@ -98,7 +155,8 @@ impl MapModel {
         });
 
         if is_osm {
-            let profile: Profile = serde_wasm_bindgen::from_value(profile)?;
+            let profile: profiles::ProfileInput = serde_wasm_bindgen::from_value(profile)?;
+            let profile = profile.resolve().map_err(err_to_js)?;
             MapModel::create(input_bytes, profile).map_err(err_to_js)
         } else {
             info!("Deserializing MapModel from {} bytes", input_bytes.len());
@@ -114,8 +172,20 @@ impl MapModel {
         for r in &self.graph.roads {
             let mut f = self.graph.mercator.to_wgs84_gj(&r.linestring);
             f.set_property("kind", format!("{:?}", self.road_kinds[r.id.0]));
+            f.set_property("access", format!("{:?}", self.access[r.id.0]));
+            if let Some(reason) = self.step_free[r.id.0].reason() {
+                f.set_property("step_free", reason);
+            }
             f.set_property("url", r.way.to_string());
-            f.set_property("gradient", self.gradients[r.id.0]);
+            let slope = self.slopes[r.id.0];
+            f.set_property("gradient", slope.gradient);
+            f.set_property("ascent_m", slope.ascent_m);
+            f.set_property("descent_m", slope.descent_m);
+            f.set_property("max_slope", slope.max_slope);
+            let comfort = &self.comfort[r.id.0];
+            f.set_property("comfort", comfort.score);
+            f.set_property("traffic_stress", comfort.traffic_stress);
+            f.set_property("comfort_reasons", comfort.describe_reasons().join(", "));
             features.push(f);
         }
 
@@ -130,15 +200,89 @@ impl MapModel {
         for c in &self.crossings {
             let mut f = self.graph.mercator.to_wgs84_gj(&Point::from(c.point));
             for (k, v) in &c.tags.0 {
-                f.set_property("url", c.osm_id.to_string());
-                f.set_property("kind", format!("{:?}", c.kind));
                 f.set_property(k, v.to_string());
             }
+            f.set_property("url", c.url.clone());
+            f.set_property("kind", format!("{:?}", c.kind));
+            f.set_property("filter_key", c.filter_key());
+            f.set_property("cluster", c.cluster);
+            f.set_property("island", c.island);
+            f.set_property("stages", c.stages);
+            if let Some(timing) = c.timing {
+                f.set_property("cycle_s", timing.cycle_s);
+                f.set_property("green_s", timing.green_s);
+                f.set_property("timing_source", format!("{:?}", timing.source));
+            }
+            features.push(f);
+        }
+        Ok(serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)?)
+    }
+
+    /// Returns GeoJSON with the barriers that restrict some road
+    #[wasm_bindgen(js_name = getBarriers)]
+    pub fn get_barriers(&self) -> Result<String, JsValue> {
+        let mut features = Vec::new();
+        for b in &self.barriers {
+            let mut f = self.graph.mercator.to_wgs84_gj(&b.geometry);
+            for (k, v) in &b.tags.0 {
+                f.set_property(k, v.to_string());
+            }
+            f.set_property("url", b.url.clone());
+            f.set_property("access", format!("{:?}", b.access));
+            f.set_property("num_roads", b.roads.len());
             features.push(f);
         }
         Ok(serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)?)
     }
 
+    /// Returns GeoJSON with buildings that couldn't be assigned to a road for some profile, or were
+    /// assigned far away
+    #[wasm_bindgen(js_name = getBuildingProblems)]
+    pub fn get_building_problems(&self) -> Result<String, JsValue> {
+        let mut features = Vec::new();
+        for problem in &self.building_problems {
+            let mut f = self
+                .graph
+                .mercator
+                .to_wgs84_gj(&self.buildings[problem.building]);
+            f.set_property("profile", problem.profile.clone());
+            if let Some(distance) = problem.distance {
+                f.set_property("distance", distance);
+            }
+            features.push(f);
+
+            if let Some(ref connector) = problem.connector {
+                let mut f = self.graph.mercator.to_wgs84_gj(connector);
+                f.set_property("profile", problem.profile.clone());
+                f.set_property("distance", problem.distance);
+                features.push(f);
+            }
+        }
+        Ok(serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)?)
+    }
+
+    /// Returns a GeoJSON report of possible tagging problems, with links to OSM
+    #[wasm_bindgen(js_name = getIssues)]
+    pub fn get_issues(&self) -> Result<String, JsValue> {
+        qa::report(self).map_err(err_to_js)
+    }
+
+    /// Overrides the timing of signalized crossings from CSV with `node_id,cycle_s,green_s`
+    /// columns. Returns how many crossings matched.
+    #[wasm_bindgen(js_name = setSignalTimings)]
+    pub fn set_signal_timings_js(&mut self, csv: String) -> Result<usize, JsValue> {
+        self.set_signal_timings(&csv).map_err(err_to_js)
+    }
+
+    /// Returns JSON with the default settings for the profile's country
+    #[wasm_bindgen(js_name = getDefaultSettings)]
+    pub fn get_default_settings(&self) -> Result<String, JsValue> {
+        Ok(
+            serde_json::to_string(&Settings::for_country(self.country.as_deref()))
+                .map_err(err_to_js)?,
+        )
+    }
+
     #[wasm_bindgen(js_name = getCrossingDistances)]
     pub fn get_crossing_distances(&self, include_kinds: Vec<String>) -> Result<String, JsValue> {
         Ok(
@@ -158,7 +302,9 @@ impl MapModel {
             x: req.x2,
             y: req.y2,
         });
-        let (_, gj) = route::do_route(self, start, end, req.settings).map_err(err_to_js)?;
+        let settings = self.resolve_settings(req.settings, req.persona);
+        let (_, gj) =
+            route::do_route(self, start, end, settings, req.departure).map_err(err_to_js)?;
         let out = serde_json::to_string(&gj).map_err(err_to_js)?;
         Ok(out)
     }
@@ -170,24 +316,81 @@ impl MapModel {
             .graph
             .mercator
             .pt_to_mercator(Coord { x: req.x, y: req.y });
+        let settings1 = self.resolve_settings(req.settings1, req.persona1);
+        let settings2 = if req.settings2.is_some() || req.persona2.is_some() {
+            Some(self.resolve_settings(req.settings2, req.persona2))
+        } else {
+            None
+        };
         let gj = self.calculate_isochrone(
             start,
             req.style,
             req.time_limit,
-            req.settings1,
-            req.settings2,
+            settings1,
+            settings2,
+            req.departure,
         );
         let out = serde_json::to_string(&gj).map_err(err_to_js)?;
         Ok(out)
     }
 
+    /// Scores detours at each departure time, so time periods can be compared
     #[wasm_bindgen(js_name = scoreDetours)]
-    pub fn score_detours(&mut self) -> Result<String, JsValue> {
-        let samples = scores::calculate(self, Settings::uk());
+    pub fn score_detours(&mut self, input: JsValue) -> Result<String, JsValue> {
+        let req: ScoreRequest = serde_wasm_bindgen::from_value(input)?;
+        let settings = self.resolve_settings(req.settings, req.persona);
+        let samples = scores::calculate(self, settings, &req.departures);
         let out = serde_json::to_string(&samples).map_err(err_to_js)?;
         Ok(out)
     }
 
+    /// Returns JSON with the names of all personas
+    #[wasm_bindgen(js_name = getPersonas)]
+    pub fn get_personas(&self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&Persona::all()).map_err(err_to_js)?)
+    }
+
+    /// Returns JSON with the settings for a persona in the profile's country
+    #[wasm_bindgen(js_name = getPersonaSettings)]
+    pub fn get_persona_settings(&self, persona: String) -> Result<String, JsValue> {
+        let persona = Persona::parse(&persona).map_err(err_to_js)?;
+        Ok(
+            serde_json::to_string(&Settings::for_persona(persona, self.country.as_deref()))
+                .map_err(err_to_js)?,
+        )
+    }
+
+    /// Routes for every persona (or the ones given), comparing them to the first
+    #[wasm_bindgen(js_name = comparePersonasRoute)]
+    pub fn compare_personas_route_js(&mut self, input: JsValue) -> Result<String, JsValue> {
+        let req: PersonasRouteRequest = serde_wasm_bindgen::from_value(input)?;
+        let start = self.graph.mercator.pt_to_mercator(Coord {
+            x: req.x1,
+            y: req.y1,
+        });
+        let end = self.graph.mercator.pt_to_mercator(Coord {
+            x: req.x2,
+            y: req.y2,
+        });
+        let personas = req.personas.unwrap_or_else(Persona::all);
+        let gj = self.compare_personas_route(start, end, &personas, req.departure);
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Calculates an isochrone for every persona (or the ones given), with each persona's cost
+    /// per road
+    #[wasm_bindgen(js_name = comparePersonasIsochrone)]
+    pub fn compare_personas_isochrone_js(&mut self, input: JsValue) -> Result<String, JsValue> {
+        let req: PersonasIsochroneRequest = serde_wasm_bindgen::from_value(input)?;
+        let start = self
+            .graph
+            .mercator
+            .pt_to_mercator(Coord { x: req.x, y: req.y });
+        let personas = req.personas.unwrap_or_else(Persona::all);
+        let gj = self.compare_personas_isochrone(start, req.time_limit, &personas, req.departure);
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
     /// Return a polygon covering the world, minus a hole for the boundary, in WGS84
     #[wasm_bindgen(js_name = getInvertedBoundary)]
     pub fn get_inverted_boundary(&self) -> Result<String, JsValue> {
@@ -214,8 +417,22 @@ impl MapModel {
         &self.graph
     }
 
-    pub fn set_gradients(&mut self, gradients: Vec<f64>) {
-        self.gradients = gradients;
+    /// A persona's settings if one is given, then explicit settings, then the country's defaults
+    pub fn resolve_settings(
+        &self,
+        settings: Option<Settings>,
+        persona: Option<Persona>,
+    ) -> Settings {
+        match (persona, settings) {
+            (Some(persona), _) => Settings::for_persona(persona, self.country.as_deref()),
+            (None, Some(settings)) => settings,
+            (None, None) => Settings::for_country(self.country.as_deref()),
+        }
+    }
+
+    /// Returns a GeoJSON report of possible tagging problems
+    pub fn qa_report(&self) -> anyhow::Result<String> {
+        qa::report(self)
     }
 }
 
@@ -227,17 +444,60 @@ struct CompareRouteRequest {
     y1: f64,
     x2: f64,
     y2: f64,
-    settings: Settings,
+    settings: Option<Settings>,
+    /// Overrides `settings`
+    persona: Option<Persona>,
+    #[serde(default)]
+    departure: Departure,
+}
+
+#[derive(Deserialize)]
+struct PersonasRouteRequest {
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    /// Every persona if missing
+    personas: Option<Vec<Persona>>,
+    #[serde(default)]
+    departure: Departure,
+}
+
+#[derive(Deserialize)]
+struct PersonasIsochroneRequest {
+    x: f64,
+    y: f64,
+    time_limit: u64,
+    /// Every persona if missing
+    personas: Option<Vec<Persona>>,
+    #[serde(default)]
+    departure: Departure,
+}
+
+#[derive(Deserialize)]
+struct ScoreRequest {
+    /// Defaults for the country if missing
+    settings: Option<Settings>,
+    /// Overrides `settings`
+    persona: Option<Persona>,
+    /// At least one
+    departures: Vec<Departure>,
 }
 
 #[derive(Deserialize)]
 struct IsochroneRequest {
     x: f64,
     y: f64,
-    settings1: Settings,
+    settings1: Option<Settings>,
+    /// Overrides `settings1`
+    persona1: Option<Persona>,
     settings2: Option<Settings>,
+    /// Overrides `settings2`
+    persona2: Option<Persona>,
     style: isochrone::Style,
     time_limit: u64,
+    #[serde(default)]
+    departure: Departure,
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
@@ -245,21 +505,71 @@ pub struct Settings {
     obey_crossings: bool,
     base_speed_mph: f64,
     use_gradient: bool,
-    delay_signalized: f64,
-    delay_zebra: f64,
-    delay_other: f64,
+    /// Multiplies gradients before adjusting speed, for people more affected by slopes
+    gradient_factor: f64,
+    /// Seconds spent waiting at each kind of crossing, or climbing up and down a footbridge or
+    /// underpass on top of walking its length. Missing kinds have no delay.
+    crossing_delays: BTreeMap<CrossingKind, f64>,
+    /// Each stage after the first of a multi-stage crossing adds this fraction of the usual delay
+    extra_stage_factor: f64,
+    /// Whether signalized crossings use `crossing_delays` or a wait from their signal timing
+    signal_model: signals::SignalModel,
+    /// The signal timing of crossings without their own, in seconds
+    signal_cycle_s: f64,
+    signal_green_s: f64,
+    /// How much to avoid uncomfortable roads. The walking time along a road is multiplied by 1 plus
+    /// this times how far its comfort score is below 1, so 0 ignores comfort.
+    #[serde(default)]
+    comfort_factor: f64,
+    /// Multiplies the walking time along roads with permissive access
+    permissive_penalty: f64,
+    /// Seconds added to routes passing through an area of roads only meant as a destination
+    destination_penalty: f64,
+    /// Avoid steps, raised kerbs, steep inclines, and so on
+    step_free: bool,
+    /// In step-free mode, multiplies the walking time along roads that're usable with difficulty
+    limited_penalty: f64,
+    /// Seconds spent waiting for and riding an elevator
+    elevator_delay: f64,
+    /// Along escalators and moving walkways, including people walking on them
+    escalator_speed_mph: f64,
+    /// Crossings of these kinds can't be used
+    #[serde(default)]
+    disabled_crossings: BTreeSet<CrossingKind>,
+    /// Changes to these settings for parts of the day. The first band covering a departure time
+    /// applies.
+    #[serde(default)]
+    time_bands: Vec<time_bands::TimeBand>,
 }
 
 impl Settings {
     pub fn uk() -> Self {
+        Self::for_country(Some("GB"))
+    }
+
+    /// Defaults for an ISO 3166-1 alpha-2 country code
+    pub fn for_country(country: Option<&str>) -> Self {
+        let (signal_cycle_s, signal_green_s) = signals::default_timing(country);
         Self {
             obey_crossings: true,
             base_speed_mph: 3.0,
             // TODO Trickier to enable by default.
             use_gradient: false,
-            delay_signalized: 30.0,
-            delay_zebra: 0.0,
-            delay_other: 10.0,
+            gradient_factor: 1.0,
+            crossing_delays: crossings::default_delays(country),
+            extra_stage_factor: 0.5,
+            comfort_factor: 0.0,
+            signal_model: signals::SignalModel::Flat,
+            signal_cycle_s,
+            signal_green_s,
+            permissive_penalty: 1.5,
+            destination_penalty: 600.0,
+            step_free: false,
+            limited_penalty: 2.0,
+            elevator_delay: 45.0,
+            escalator_speed_mph: 1.5,
+            disabled_crossings: BTreeSet::new(),
+            time_bands: Vec::new(),
         }
     }
 }
@@ -272,24 +582,85 @@ fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
 pub fn cost(
     road_linestring: &LineString,
     kind: RoadKind,
-    gradient: f64,
+    slope: Slope,
+    share: CrossingShare,
     settings: &Settings,
 ) -> (Duration, Duration) {
-    let speed = if settings.use_gradient {
-        walking_speed_on_incline(settings.base_speed_mph, gradient)
+    let length = Euclidean.length(road_linestring);
+    let active = Duration::from_secs_f64(if kind == RoadKind::Elevator {
+        // The short stub isn't walked; an elevator only costs the wait
+        0.0
+    } else if kind == RoadKind::Escalator {
+        length / mph_to_mps(settings.escalator_speed_mph)
+    } else if settings.use_gradient {
+        slope
+            .split(length)
+            .into_iter()
+            .filter(|(part_length, _)| *part_length > 0.0)
+            .map(|(part_length, gradient)| {
+                part_length
+                    / walking_speed_on_incline(
+                        settings.base_speed_mph,
+                        gradient * settings.gradient_factor,
+                    )
+            })
+            .sum()
     } else {
-        mph_to_mps(settings.base_speed_mph)
-    };
-    let active = Duration::from_secs_f64(Euclidean.length(road_linestring) / speed);
+        length / mph_to_mps(settings.base_speed_mph)
+    });
     let waiting = Duration::from_secs_f64(match kind {
-        RoadKind::Crossing(CrossingKind::Signalized) => settings.delay_signalized,
-        RoadKind::Crossing(CrossingKind::Zebra) => settings.delay_zebra,
-        RoadKind::Crossing(CrossingKind::Other) => settings.delay_other,
+        RoadKind::Crossing(kind) => share.delay(
+            signals::signal_wait(kind, share.timing, settings)
+                .map(|(wait, _)| wait)
+                .unwrap_or_else(|| settings.crossing_delays.get(&kind).cloned().unwrap_or(0.0)),
+            settings.extra_stage_factor,
+        ),
+        RoadKind::Elevator => settings.elevator_delay,
         _ => 0.0,
     });
     (active, waiting)
 }
 
+/// Rather than change the graph's access when settings or the departure time change, make roads
+/// that shouldn't be used prohibitively expensive
+pub(crate) const PROHIBITIVE_COST: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The cost used to find routes and isochrones, including penalties for roads with restricted
+/// access, that're hard to use in step-free mode, or that're uncomfortable. Reported durations use
+/// `cost` instead.
+pub fn routing_cost(
+    road_linestring: &LineString,
+    kind: RoadKind,
+    slope: Slope,
+    share: CrossingShare,
+    access: Access,
+    step_free: &StepFree,
+    comfort: f64,
+    settings: &Settings,
+) -> Duration {
+    if matches!(kind, RoadKind::Crossing(kind) if settings.disabled_crossings.contains(&kind)) {
+        return PROHIBITIVE_COST;
+    }
+    let (mut active, waiting) = cost(road_linestring, kind, slope, share, settings);
+    active = active.mul_f64(1.0 + settings.comfort_factor * (1.0 - comfort));
+    if settings.step_free {
+        match step_free {
+            StepFree::Usable => {}
+            StepFree::Limited(_) => {
+                active = active.mul_f64(settings.limited_penalty);
+            }
+            StepFree::Unusable(_) => {
+                return PROHIBITIVE_COST;
+            }
+        }
+    }
+    match access {
+        // Passing through is penalized once per destination-only area, while searching
+        Access::Public | Access::Excluded | Access::Destination => active + waiting,
+        Access::Permissive => active.mul_f64(settings.permissive_penalty) + waiting,
+    }
+}
+
 // Returns m/s. https://en.wikipedia.org/wiki/Tobler%27s_hiking_function
 fn walking_speed_on_incline(base_speed_mph: f64, gradient: f64) -> f64 {
     let exponent = -3.5 * ((gradient / 100.0) + 0.05).abs();
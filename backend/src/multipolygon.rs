@@ -0,0 +1,69 @@
+use geo::{Contains, Coord, LineString, Polygon};
+
+/// Joins the outer and inner member ways of a multipolygon relation into polygons. Each inner ring
+/// becomes a hole in the outer ring containing it. Ways that don't join up into a closed ring are
+/// dropped.
+pub fn build_multipolygon(outers: Vec<Vec<Coord>>, inners: Vec<Vec<Coord>>) -> Vec<Polygon> {
+    let mut inner_rings = join_rings(inners);
+    let mut polygons = Vec::new();
+    for outer in join_rings(outers) {
+        let outer_polygon = Polygon::new(outer.clone(), Vec::new());
+        let (holes, rest): (Vec<_>, Vec<_>) = inner_rings
+            .into_iter()
+            .partition(|ring| outer_polygon.contains(&ring.0[0]));
+        inner_rings = rest;
+        polygons.push(Polygon::new(outer, holes));
+    }
+    polygons
+}
+
+fn join_rings(mut ways: Vec<Vec<Coord>>) -> Vec<LineString> {
+    ways.retain(|pts| pts.len() >= 2);
+    let mut rings = Vec::new();
+    while let Some(mut current) = ways.pop() {
+        loop {
+            if current.len() >= 4 && current[0] == current[current.len() - 1] {
+                rings.push(LineString::new(current));
+                break;
+            }
+            let end = current[current.len() - 1];
+            let Some(idx) = ways
+                .iter()
+                .position(|pts| pts[0] == end || pts[pts.len() - 1] == end)
+            else {
+                warn!("Multipolygon has a ring that doesn't close");
+                break;
+            };
+            let mut next = ways.swap_remove(idx);
+            if next[0] != end {
+                next.reverse();
+            }
+            current.extend(next.into_iter().skip(1));
+        }
+    }
+    rings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_multipolygon() {
+        let c = |x, y| Coord { x, y };
+        // A square split into two ways, one reversed, with a triangular hole
+        let outers = vec![
+            vec![c(0.0, 0.0), c(10.0, 0.0), c(10.0, 10.0)],
+            vec![c(0.0, 0.0), c(0.0, 10.0), c(10.0, 10.0)],
+        ];
+        let inners = vec![vec![c(1.0, 1.0), c(2.0, 1.0), c(1.0, 2.0), c(1.0, 1.0)]];
+        let polygons = build_multipolygon(outers, inners);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].exterior().0.len(), 5);
+        assert_eq!(polygons[0].interiors().len(), 1);
+
+        // Dangling ways are dropped
+        let outers = vec![vec![c(0.0, 0.0), c(10.0, 0.0), c(10.0, 10.0)]];
+        assert!(build_multipolygon(outers, Vec::new()).is_empty());
+    }
+}
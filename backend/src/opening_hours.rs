@@ -0,0 +1,254 @@
+use std::collections::BTreeSet;
+
+use chrono::{NaiveTime, Timelike, Weekday};
+use graph::RoadID;
+use serde::{Deserialize, Serialize};
+
+use crate::MapModel;
+
+const END_OF_DAY: u32 = 24 * 60;
+// Sun times vary by place and season; just use something plausible
+const SUNRISE: u32 = 6 * 60;
+const SUNSET: u32 = 20 * 60;
+const DAWN: u32 = 5 * 60 + 30;
+const DUSK: u32 = 20 * 60 + 30;
+
+/// When a route or isochrone starts
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Departure {
+    pub weekday: Weekday,
+    pub time: NaiveTime,
+}
+
+impl Default for Departure {
+    fn default() -> Self {
+        Self {
+            weekday: Weekday::Mon,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+        }
+    }
+}
+
+/// A parsed `opening_hours` tag. Only a common subset of the syntax is supported: weekday ranges,
+/// time spans (including ones past midnight), `off`, `24/7`, and approximate sun times. Public
+/// holiday rules are ignored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OpeningHours {
+    /// The original tag value
+    pub raw: String,
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Rule {
+    /// Indexed from Monday
+    days: [bool; 7],
+    /// Minutes since midnight. The end may be past midnight. Empty means closed.
+    spans: Vec<(u32, u32)>,
+}
+
+impl OpeningHours {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut rules = Vec::new();
+        for part in raw.split(';') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+            if part == "24/7" {
+                rules.push(Rule {
+                    days: [true; 7],
+                    spans: vec![(0, END_OF_DAY)],
+                });
+                continue;
+            }
+
+            let (days, times) = match part.split_once(' ') {
+                Some((selector, times)) if selector.starts_with(char::is_alphabetic) => {
+                    // Skip rules that only apply to holidays
+                    let Some(days) = parse_days(selector)? else {
+                        continue;
+                    };
+                    (days, times.trim())
+                }
+                _ => ([true; 7], part),
+            };
+
+            let spans = if times == "off" || times == "closed" {
+                Vec::new()
+            } else {
+                times
+                    .split(',')
+                    .map(|span| parse_span(span.trim()))
+                    .collect::<Option<Vec<_>>>()?
+            };
+            rules.push(Rule { days, spans });
+        }
+
+        if rules.is_empty() {
+            return None;
+        }
+        Some(Self {
+            raw: raw.to_string(),
+            rules,
+        })
+    }
+
+    pub fn is_open(&self, departure: Departure) -> bool {
+        let day = departure.weekday.num_days_from_monday() as usize;
+        let yesterday = (day + 6) % 7;
+        let minute = departure.time.hour() * 60 + departure.time.minute();
+
+        // Later rules override earlier ones for the days they cover
+        let mut open = false;
+        for rule in &self.rules {
+            if rule.days[day] {
+                open = rule
+                    .spans
+                    .iter()
+                    .any(|(start, end)| *start <= minute && minute < *end);
+            }
+        }
+        if open {
+            return true;
+        }
+
+        // Spans from the previous day might continue past midnight
+        self.rules.iter().any(|rule| {
+            rule.days[yesterday]
+                && rule
+                    .spans
+                    .iter()
+                    .any(|(start, end)| *start <= minute + END_OF_DAY && minute + END_OF_DAY < *end)
+        })
+    }
+}
+
+/// Returns `Some(None)` for selectors that only cover holidays
+fn parse_days(selector: &str) -> Option<Option<[bool; 7]>> {
+    let mut days = [false; 7];
+    let mut any = false;
+    for part in selector.split(',') {
+        if part == "PH" || part == "SH" {
+            continue;
+        }
+        any = true;
+        if let Some((from, to)) = part.split_once('-') {
+            let from = parse_day(from)?;
+            let to = parse_day(to)?;
+            let mut day = from;
+            loop {
+                days[day] = true;
+                if day == to {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        } else {
+            days[parse_day(part)?] = true;
+        }
+    }
+    Some(any.then_some(days))
+}
+
+fn parse_day(day: &str) -> Option<usize> {
+    ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"]
+        .iter()
+        .position(|x| *x == day)
+}
+
+fn parse_span(span: &str) -> Option<(u32, u32)> {
+    // "18:00+" means open-ended
+    if let Some(start) = span.strip_suffix('+') {
+        return Some((parse_time(start)?, END_OF_DAY));
+    }
+    let (start, end) = span.split_once('-')?;
+    let start = parse_time(start)?;
+    let mut end = parse_time(end)?;
+    if end <= start {
+        end += END_OF_DAY;
+    }
+    Some((start, end))
+}
+
+fn parse_time(time: &str) -> Option<u32> {
+    match time {
+        "sunrise" => Some(SUNRISE),
+        "sunset" => Some(SUNSET),
+        "dawn" => Some(DAWN),
+        "dusk" => Some(DUSK),
+        _ => {
+            let (hours, minutes) = time.split_once(':')?;
+            let hours: u32 = hours.parse().ok()?;
+            let minutes: u32 = minutes.parse().ok()?;
+            if hours > 24 || minutes > 59 {
+                return None;
+            }
+            Some(hours * 60 + minutes)
+        }
+    }
+}
+
+impl MapModel {
+    /// Roads with opening hours (on the way itself, a barrier along it, or a park it's inside)
+    /// that are closed at this time. Remembers the last departure, since routes and scores ask
+    /// about the same one many times.
+    pub fn closed_roads(&mut self, departure: Departure) -> BTreeSet<RoadID> {
+        if let Some((_, closed)) = self
+            .closed_at
+            .as_ref()
+            .filter(|(cached, _)| *cached == departure)
+        {
+            return closed.clone();
+        }
+        let closed: BTreeSet<RoadID> = self
+            .opening_hours
+            .iter()
+            .filter(|(_, hours)| hours.iter().any(|oh| !oh.is_open(departure)))
+            .map(|(r, _)| *r)
+            .collect();
+        self.closed_at = Some((departure, closed.clone()));
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opening_hours() {
+        let cases = [
+            ("24/7", "Sun", (3, 0), true),
+            ("Mo-Fr 08:00-18:00", "Mon", (7, 59), false),
+            ("Mo-Fr 08:00-18:00", "Fri", (8, 0), true),
+            ("Mo-Fr 08:00-18:00", "Sat", (12, 0), false),
+            ("Mo-Fr 08:00-18:00; Sa,Su 10:00-16:00", "Sun", (12, 0), true),
+            ("Mo-Su 07:00-22:00; Su off", "Sun", (12, 0), false),
+            ("Mo-Su 07:00-22:00; PH off", "Sun", (12, 0), true),
+            ("Fr-Mo 10:00-12:00", "Sun", (11, 0), true),
+            ("Fr-Mo 10:00-12:00", "Wed", (11, 0), false),
+            ("sunrise-sunset", "Tue", (21, 0), false),
+            ("Mo-Sa 22:00-02:00", "Sun", (1, 0), true),
+            ("Mo-Sa 22:00-02:00", "Mon", (1, 0), false),
+            ("06:00-12:00, 13:00-24:00", "Wed", (12, 30), false),
+            ("06:00-12:00, 13:00-24:00", "Wed", (23, 30), true),
+            ("18:00+", "Wed", (23, 30), true),
+        ];
+        for (raw, weekday, (hour, minute), expected) in cases {
+            let departure = Departure {
+                weekday: weekday.parse().unwrap(),
+                time: NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+            };
+            assert_eq!(
+                OpeningHours::parse(raw).unwrap().is_open(departure),
+                expected,
+                "{raw} on {weekday} at {hour}:{minute}"
+            );
+        }
+
+        for raw in ["Apr-Oct 08:00-20:00", "Mo-Fr", "by appointment", ""] {
+            assert_eq!(OpeningHours::parse(raw), None, "{raw}");
+        }
+    }
+}
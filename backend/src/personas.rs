@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use geo::Coord;
+use geojson::{Feature, FeatureCollection};
+use graph::RoadID;
+use serde::{Deserialize, Serialize};
+
+use crate::{CrossingKind, Departure, MapModel, Settings};
+
+/// A kind of person walking, with the settings that usually suit them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Persona {
+    AbleBodiedAdult,
+    OlderAdult,
+    Child,
+    /// Pushing a pushchair or pram
+    Pushchair,
+    Crutches,
+    Wheelchair,
+}
+
+impl Persona {
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::AbleBodiedAdult,
+            Self::OlderAdult,
+            Self::Child,
+            Self::Pushchair,
+            Self::Crutches,
+            Self::Wheelchair,
+        ]
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match Self::all().into_iter().find(|p| format!("{p:?}") == name) {
+            Some(persona) => Ok(persona),
+            None => bail!("Unknown persona {name}; choose from {:?}", Self::all()),
+        }
+    }
+}
+
+impl Settings {
+    /// Defaults for a persona in a country. Everybody starts from the country's defaults, then
+    /// walking speed, sensitivity to gradients, waits for a gap in traffic, step handling, and
+    /// willingness to cross informally change.
+    pub fn for_persona(persona: Persona, country: Option<&str>) -> Self {
+        let mut settings = Self::for_country(country);
+        match persona {
+            Persona::AbleBodiedAdult => {}
+            Persona::OlderAdult => {
+                slower_gaps(&mut settings, 1.5);
+                settings.base_speed_mph = 2.5;
+                settings.use_gradient = true;
+                settings.gradient_factor = 1.5;
+            }
+            Persona::Child => {
+                slower_gaps(&mut settings, 2.0);
+                settings.base_speed_mph = 2.5;
+                settings.disabled_crossings.insert(CrossingKind::Informal);
+            }
+            Persona::Pushchair => {
+                slower_gaps(&mut settings, 1.5);
+                settings.base_speed_mph = 2.5;
+                settings.use_gradient = true;
+                settings.gradient_factor = 1.5;
+                settings.step_free = true;
+                settings.limited_penalty = 1.5;
+                settings.disabled_crossings.insert(CrossingKind::Informal);
+            }
+            Persona::Crutches => {
+                slower_gaps(&mut settings, 2.0);
+                settings.base_speed_mph = 1.5;
+                settings.use_gradient = true;
+                settings.gradient_factor = 2.0;
+                settings.disabled_crossings.insert(CrossingKind::Informal);
+            }
+            Persona::Wheelchair => {
+                slower_gaps(&mut settings, 1.5);
+                settings.base_speed_mph = 2.0;
+                settings.use_gradient = true;
+                settings.gradient_factor = 2.0;
+                settings.step_free = true;
+                settings.limited_penalty = 3.0;
+                settings.disabled_crossings.insert(CrossingKind::Informal);
+            }
+        }
+        settings
+    }
+}
+
+/// Waiting for a gap in traffic takes longer for people who need a bigger one
+fn slower_gaps(settings: &mut Settings, factor: f64) {
+    for kind in [
+        CrossingKind::Marked,
+        CrossingKind::Refuge,
+        CrossingKind::Unmarked,
+        CrossingKind::Informal,
+        CrossingKind::Other,
+    ] {
+        if let Some(delay) = settings.crossing_delays.get_mut(&kind) {
+            *delay *= factor;
+        }
+    }
+}
+
+/// How one persona's route compares to the first persona's
+#[derive(Serialize)]
+struct RouteComparison {
+    persona: Persona,
+    /// `None` if there's no route
+    route_length: Option<f64>,
+    duration_s: Option<u64>,
+    waiting_duration_s: Option<u64>,
+    /// Compared to the first persona
+    extra_duration_s: Option<i64>,
+    /// Does this persona use any way the first persona doesn't, or vice versa?
+    different_route: Option<bool>,
+}
+
+impl MapModel {
+    /// Routes between two points for each persona, returning every route and a comparison to the
+    /// first persona
+    pub fn compare_personas_route(
+        &mut self,
+        start: Coord,
+        end: Coord,
+        personas: &[Persona],
+        departure: Departure,
+    ) -> FeatureCollection {
+        let mut features = Vec::new();
+        let mut comparisons = Vec::new();
+        let mut baseline: Option<(u64, HashSet<String>)> = None;
+        for persona in personas {
+            let settings = Settings::for_persona(*persona, self.country.as_deref());
+            let Ok((_, fc)) = crate::route::do_route(self, start, end, settings, departure) else {
+                comparisons.push(RouteComparison {
+                    persona: *persona,
+                    route_length: None,
+                    duration_s: None,
+                    waiting_duration_s: None,
+                    extra_duration_s: None,
+                    different_route: None,
+                });
+                continue;
+            };
+            let info = fc.foreign_members.clone().unwrap();
+            let active = info["active_duration_s"].as_u64().unwrap();
+            let waiting = info["waiting_duration_s"].as_u64().unwrap();
+            let ways: HashSet<String> = info["directions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|step| step["way"].as_str().unwrap().to_string())
+                .collect();
+            let duration = active + waiting;
+
+            let (extra, different) = match baseline {
+                Some((baseline_duration, ref baseline_ways)) => (
+                    Some(duration as i64 - baseline_duration as i64),
+                    Some(ways != *baseline_ways),
+                ),
+                None => (Some(0), Some(false)),
+            };
+            // The first persona with a route is the baseline
+            if baseline.is_none() {
+                baseline = Some((duration, ways));
+            }
+            comparisons.push(RouteComparison {
+                persona: *persona,
+                route_length: info["route_length"].as_f64(),
+                duration_s: Some(duration),
+                waiting_duration_s: Some(waiting),
+                extra_duration_s: extra,
+                different_route: different,
+            });
+
+            for mut f in fc.features {
+                f.set_property("persona", format!("{persona:?}"));
+                f.set_property("duration_s", duration);
+                features.push(f);
+            }
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({ "personas": comparisons })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+        }
+    }
+
+    /// Calculates an isochrone for each persona, returning every reachable road with the cost for
+    /// each persona, and how many roads each persona reaches
+    pub fn compare_personas_isochrone(
+        &mut self,
+        start: Coord,
+        time_limit_mins: u64,
+        personas: &[Persona],
+        departure: Departure,
+    ) -> FeatureCollection {
+        let limit = Duration::from_secs(time_limit_mins * 60);
+        let mut costs: BTreeMap<RoadID, BTreeMap<String, u64>> = BTreeMap::new();
+        let mut reached: BTreeMap<String, usize> = BTreeMap::new();
+        for persona in personas {
+            let settings = Settings::for_persona(*persona, self.country.as_deref());
+            let profile = self.prepare_profile(settings, departure);
+            let snapped = self.graph.snap_to_road(start, profile);
+            let cost_per_road = self.directed_costs(snapped, profile, limit);
+            reached.insert(format!("{persona:?}"), cost_per_road.len());
+            for (r, cost) in cost_per_road {
+                costs
+                    .entry(r)
+                    .or_default()
+                    .insert(format!("{persona:?}"), cost.as_secs());
+            }
+        }
+
+        let mut features = Vec::new();
+        for (r, per_persona) in costs {
+            let mut f: Feature = self
+                .graph
+                .mercator
+                .to_wgs84_gj(&self.graph.roads[r.0].linestring);
+            f.set_property("num_personas", per_persona.len());
+            f.set_property("costs", serde_json::to_value(per_persona).unwrap());
+            features.push(f);
+        }
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({ "reached_roads": reached })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_personas() {
+        assert_eq!(Persona::parse("Wheelchair").unwrap(), Persona::Wheelchair);
+        assert!(Persona::parse("Unicycle").is_err());
+
+        let adult = Settings::for_persona(Persona::AbleBodiedAdult, Some("GB"));
+        assert!(adult == Settings::uk());
+        let wheelchair = Settings::for_persona(Persona::Wheelchair, Some("GB"));
+        assert!(wheelchair.step_free);
+        assert!(
+            wheelchair
+                .disabled_crossings
+                .contains(&CrossingKind::Informal)
+        );
+        assert!(
+            wheelchair.crossing_delays[&CrossingKind::Unmarked]
+                > adult.crossing_delays[&CrossingKind::Unmarked]
+        );
+    }
+}
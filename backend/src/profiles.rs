@@ -1,198 +1,310 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
 use utils::Tags;
 
+use crate::access::{Access, classify_access, default_access_values};
+use crate::traffic::SeveranceThresholds;
 use crate::{CrossingKind, RoadKind};
 
-#[derive(Clone, Copy, PartialEq, Deserialize)]
-pub enum Profile {
-    SeparateWays,
-    SidewalksOnHighways,
-    USA,
-    USAShoulders,
+/// A data-driven set of rules to classify OSM ways as a RoadKind. The rules are checked in order,
+/// and the first one to match decides the outcome. Every city has different street design and
+/// tagging conventions, so a new study area can start from one of the built-in profiles and adjust
+/// it, without changing any code.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub rules: Vec<Rule>,
+    /// An ISO 3166-1 alpha-2 code like "GB", used to guess missing speed limits and interpret
+    /// crossing tags
+    #[serde(default)]
+    pub country: Option<String>,
+    /// Used by the `busy_traffic` matcher
+    #[serde(default)]
+    pub severance_thresholds: SeveranceThresholds,
+    /// Turn WithTraffic roads tagged with `sidewalk=left|right|both` into one Footway per side,
+    /// only connected by explicit crossings at each end of the road
+    #[serde(default)]
+    pub split_sidewalks: bool,
+    /// How to treat values of `foot=*` and `access=*`
+    #[serde(default = "default_access_values")]
+    pub access: BTreeMap<String, Access>,
 }
 
-impl Profile {
-    /// This function classifies an OSM way as a RoadKind. If it returns `None`, then the way is
-    /// totally excluded from the walking graph.
-    pub fn classify(self, tags: &Tags) -> Option<RoadKind> {
-        if !tags.has("highway")
-            || tags.is_any("highway", vec!["construction", "proposed"])
-            || tags.is("area", "yes")
-        {
-            return None;
-        }
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// Every matcher must pass. If there are none, the rule always matches.
+    #[serde(default)]
+    pub when: Vec<Matcher>,
+    pub then: Outcome,
+    /// Explains the rule; not used for anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
 
-        // Some kind of explicit footway
-        if tags.is_any(
-            "highway",
-            vec!["footway", "steps", "path", "track", "corridor"],
-        ) {
-            if tags.is("footway", "crossing") {
-                // TODO Assumes the tags are both on the way and crossing node
-                return Some(RoadKind::Crossing(CrossingKind::from_tags(tags)));
-            }
-            return Some(RoadKind::Footway);
-        }
-        if tags.is("highway", "cycleway") && tags.is("foot", "yes") {
-            if tags.is("cycleway", "crossing") {
-                return Some(RoadKind::Crossing(CrossingKind::from_tags(tags)));
-            }
-            return Some(RoadKind::Footway);
-        }
-        if tags.is("highway", "cycleway") && tags.is("foot", "no") {
-            return None;
-        }
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Matcher {
+    /// The key has any value
+    Has(String),
+    /// The key is absent
+    Missing(String),
+    /// The key has one of these values
+    IsAny(String, Vec<String>),
+    /// The key is absent or has a value other than these
+    NotAny(String, Vec<String>),
+    /// The road's speed limit, lane count, or dual carriageway status exceed the profile's
+    /// `severance_thresholds`
+    BusyTraffic,
+}
 
-        if tags.is("highway", "crossing") || tags.has("crossing") {
-            return Some(RoadKind::Crossing(CrossingKind::from_tags(tags)));
-        }
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// Totally exclude the way from the walking graph
+    Exclude,
+    Footway,
+    /// The specific CrossingKind comes from the tags
+    Crossing,
+    Severance,
+    WithTraffic,
+    PedestrianArea,
+    Elevator,
+    Escalator,
+}
 
-        if self == Profile::USA {
-            return usa(tags);
-        }
-        if self == Profile::USAShoulders {
-            return usa_shoulders(tags);
-        }
+/// Either the name of a built-in profile or a full set of rules, as passed in from the web app
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ProfileInput {
+    Builtin(String),
+    Custom(Profile),
+}
 
-        // Big roads are always severances.
-        // TODO Big roads without separate sidewalks aren't walkable at all right now.
-        // https://github.com/dabreegster/severance_snape/issues/5
-        if tags.is_any(
-            "highway",
-            vec![
-                "motorway",
-                "motorway_link",
-                "trunk",
-                "trunk_link",
-                "primary",
-                "primary_link",
-            ],
-        ) {
-            return Some(RoadKind::Severance);
+impl ProfileInput {
+    pub fn resolve(self) -> Result<Profile> {
+        match self {
+            ProfileInput::Builtin(name) => Profile::builtin(&name),
+            ProfileInput::Custom(profile) => Ok(profile),
         }
+    }
+}
 
-        // Totally exclude roads that claim to have a separately mapped sidewalk; they're just noise.
-        // I'm assuming there isn't a silly mix like "sidewalk:left = separate, sidewalk:right = yes".
-        if tags.is("sidewalk", "separate")
-            || tags.is("sidewalk:left", "separate")
-            || tags.is("sidewalk:right", "separate")
-            || tags.is("sidewalk:both", "separate")
-        {
-            return None;
-        }
+const BUILTINS: [(&str, &str); 5] = [
+    (
+        "SeparateWays",
+        include_str!("../profiles/SeparateWays.json"),
+    ),
+    (
+        "SidewalksOnHighways",
+        include_str!("../profiles/SidewalksOnHighways.json"),
+    ),
+    ("USA", include_str!("../profiles/USA.json")),
+    (
+        "USAShoulders",
+        include_str!("../profiles/USAShoulders.json"),
+    ),
+    (
+        "TrafficSpeed",
+        include_str!("../profiles/TrafficSpeed.json"),
+    ),
+];
 
-        if tags.is("highway", "pedestrian")
-            || tags.is_any("sidewalk", vec!["both", "right", "left"])
-        {
-            return Some(RoadKind::WithTraffic);
-        }
+impl Profile {
+    /// Parse a profile from JSON
+    pub fn load(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
 
-        // No sidewalk tagging. We can make a guess about which ones are still routable for walking. In
-        // places with thoroughly tagged sidewalks, disable this. Keeping this on is usually messy,
-        // because there'll be a mix of separately mapped RoadKind::Footways and then one of these
-        // RoadKind::WithTraffic in the middle.
-        if tags.is_any(
-            "highway",
-            vec![
-                "secondary",
-                "secondary_link",
-                "tertiary",
-                "tertiary_link",
-                "residential",
-                "unclassified",
-                "service",
-                "living_street",
-            ],
-        ) && !tags.is("foot", "no")
-        {
-            return match self {
-                Profile::SeparateWays => None,
-                Profile::SidewalksOnHighways => Some(RoadKind::WithTraffic),
-                Profile::USA | Profile::USAShoulders => unreachable!(),
-            };
+    pub fn builtin(name: &str) -> Result<Self> {
+        for (builtin, json) in BUILTINS {
+            if builtin == name {
+                return Self::load(json);
+            }
         }
-
-        // TODO Private?
-
-        // TODO Maybe just use tagged / assumed speed limit instead of highway classification?
-
-        // TODO wait, why's this the fallback case?
-        Some(RoadKind::Severance)
+        bail!("Unknown built-in profile {name}");
     }
-}
 
-// Footway cases already handled
-fn usa(tags: &Tags) -> Option<RoadKind> {
-    if tags.is_any(
-        "highway",
-        vec![
-            "motorway",
-            "motorway_link",
-            "trunk",
-            "trunk_link",
-            "primary",
-            "primary_link",
-            "secondary",
-            "secondary_link",
-            "tertiary",
-            "tertiary_link",
-        ],
-    ) {
-        return Some(RoadKind::Severance);
+    pub fn builtin_names() -> Vec<&'static str> {
+        BUILTINS.iter().map(|(name, _)| *name).collect()
     }
 
-    // Totally exclude roads that claim to have a separately mapped sidewalk; they're just noise.
-    // I'm assuming there isn't a silly mix like "sidewalk:left = separate, sidewalk:right = yes".
-    if tags.is("sidewalk", "separate")
-        || tags.is("sidewalk:left", "separate")
-        || tags.is("sidewalk:right", "separate")
-        || tags.is("sidewalk:both", "separate")
-    {
-        return None;
+    /// This function classifies an OSM way as a RoadKind. If it returns `None`, then the way is
+    /// totally excluded from the walking graph. That also happens if no rule matches.
+    pub fn classify(&self, tags: &Tags) -> Option<RoadKind> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.when.iter().all(|m| m.matches(tags, self)))?;
+        match rule.then {
+            Outcome::Exclude => None,
+            Outcome::Footway => Some(RoadKind::Footway),
+            Outcome::Crossing => Some(RoadKind::Crossing(CrossingKind::from_tags(
+                tags,
+                self.country.as_deref(),
+            ))),
+            Outcome::Severance => Some(RoadKind::Severance),
+            Outcome::WithTraffic => Some(RoadKind::WithTraffic),
+            Outcome::PedestrianArea => Some(RoadKind::PedestrianArea),
+            Outcome::Elevator => Some(RoadKind::Elevator),
+            Outcome::Escalator => Some(RoadKind::Escalator),
+        }
     }
 
-    // TODO
-    Some(RoadKind::WithTraffic)
+    /// Decides who may walk along a way, independently of its RoadKind
+    pub fn access(&self, tags: &Tags) -> Access {
+        classify_access(&self.access, tags)
+    }
 }
 
-fn usa_shoulders(tags: &Tags) -> Option<RoadKind> {
-    let kind = usa(tags)?;
-    if kind == RoadKind::Severance
-        && (tags.is("cycleway", "shoulder") || tags.is("cyclestreet", "yes"))
-    {
-        return Some(RoadKind::WithTraffic);
+impl Matcher {
+    fn matches(&self, tags: &Tags, profile: &Profile) -> bool {
+        match self {
+            Matcher::Has(key) => tags.has(key),
+            Matcher::Missing(key) => !tags.has(key),
+            Matcher::IsAny(key, values) => tags.get(key).is_some_and(|v| values.contains(v)),
+            Matcher::NotAny(key, values) => !tags.get(key).is_some_and(|v| values.contains(v)),
+            Matcher::BusyTraffic => profile
+                .severance_thresholds
+                .is_severance(tags, profile.country.as_deref()),
+        }
     }
-    Some(kind)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_builtins_load() {
+        for name in Profile::builtin_names() {
+            let profile = Profile::builtin(name).unwrap();
+            assert_eq!(profile.name, name);
+        }
+    }
+
+    #[test]
+    fn test_separate_ways() {
+        let separate = Profile::builtin("SeparateWays").unwrap();
+        let sidewalks = Profile::builtin("SidewalksOnHighways").unwrap();
+
+        let residential = tags(vec!["highway=residential"]);
+        assert_eq!(separate.classify(&residential), None);
+        assert_eq!(
+            sidewalks.classify(&residential),
+            Some(RoadKind::WithTraffic)
+        );
+
+        for profile in [&separate, &sidewalks] {
+            assert_eq!(
+                profile.classify(&tags(vec!["highway=residential", "foot=no"])),
+                Some(RoadKind::Severance)
+            );
+            assert_eq!(
+                profile.classify(&tags(vec!["highway=primary", "sidewalk=separate"])),
+                Some(RoadKind::Severance)
+            );
+            assert_eq!(
+                profile.classify(&tags(vec!["highway=residential", "sidewalk:left=separate"])),
+                None
+            );
+            assert_eq!(
+                profile.classify(&tags(vec!["highway=footway", "area=yes"])),
+                None
+            );
+        }
+    }
 
     #[test]
     fn test_usa() {
+        let usa = Profile::builtin("USA").unwrap();
+
         // https://www.openstreetmap.org/way/1120402115
         // TODO Maybe this should be None? It really does have crossings, so not sure
         // It's similar to https://www.openstreetmap.org/way/571968303, except by speed and lane
         // count
         assert_eq!(
-            Profile::USA.classify(&tags(vec!["highway=tertiary", "sidewalk=no",])),
+            usa.classify(&tags(vec!["highway=tertiary", "sidewalk=no",])),
             Some(RoadKind::Severance)
         );
 
         // https://www.openstreetmap.org/way/41945235
         assert_eq!(
-            Profile::USA.classify(&tags(vec!["highway=secondary", "maxspeed=40 mph",])),
+            usa.classify(&tags(vec!["highway=secondary", "maxspeed=40 mph",])),
+            Some(RoadKind::Severance)
+        );
+
+        let shoulders = Profile::builtin("USAShoulders").unwrap();
+        assert_eq!(
+            shoulders.classify(&tags(vec!["highway=secondary", "cycleway=shoulder"])),
+            Some(RoadKind::WithTraffic)
+        );
+        assert_eq!(
+            shoulders.classify(&tags(vec![
+                "highway=footway",
+                "footway=crossing",
+                "crossing=traffic_signals"
+            ])),
+            Some(RoadKind::Crossing(CrossingKind::Signalized))
+        );
+    }
+
+    #[test]
+    fn test_traffic_speed() {
+        let profile = Profile::builtin("TrafficSpeed").unwrap();
+
+        // The two USA cases differ by speed and lane count
+        assert_eq!(
+            profile.classify(&tags(vec!["highway=tertiary", "sidewalk=no"])),
+            Some(RoadKind::WithTraffic)
+        );
+        assert_eq!(
+            profile.classify(&tags(vec!["highway=secondary", "maxspeed=40 mph"])),
+            Some(RoadKind::Severance)
+        );
+        assert_eq!(
+            profile.classify(&tags(vec![
+                "highway=primary",
+                "maxspeed=20 mph",
+                "lanes=2",
+                "sidewalk=separate"
+            ])),
+            None
+        );
+        assert_eq!(
+            profile.classify(&tags(vec!["highway=trunk", "maxspeed=30 mph"])),
             Some(RoadKind::Severance)
         );
     }
 
-    fn tags(kv: Vec<&str>) -> Tags {
-        let mut tags = Tags::empty();
-        for pair in kv {
-            let parts = pair.split('=').collect::<Vec<_>>();
-            tags.insert(parts[0], parts[1]);
+    #[test]
+    fn test_access() {
+        let profile = Profile::builtin("SeparateWays").unwrap();
+        let cases = [
+            (vec!["highway=footway"], Access::Public),
+            (vec!["highway=footway", "access=private"], Access::Excluded),
+            (
+                vec!["highway=service", "access=private", "foot=yes"],
+                Access::Public,
+            ),
+            (vec!["highway=footway", "foot=private"], Access::Excluded),
+            (
+                vec!["highway=service", "access=customers"],
+                Access::Destination,
+            ),
+            (
+                vec!["highway=path", "foot=destination"],
+                Access::Destination,
+            ),
+            (vec!["highway=path", "foot=permissive"], Access::Permissive),
+            (
+                vec!["highway=path", "foot=unknown", "access=no"],
+                Access::Excluded,
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(profile.access(&tags(input)), expected);
         }
-        tags
     }
 }
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use geo::{BoundingRect, Closest, ClosestPoint, Geometry, Line, Point};
+use geojson::GeoJson;
+use graph::{Graph, IntersectionID, RoadID};
+use rstar::primitives::GeomWithData;
+use rstar::{AABB, RTree};
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::grade_separation::proper_crossing;
+use crate::{MapModel, RoadKind, Slope};
+
+/// A sidewalk ending closer than this to another path probably should connect to it
+const DANGLING_M: f64 = 3.0;
+/// Gradients steeper than this are probably bad elevation data
+const UNUSUAL_GRADIENT: f64 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IssueKind {
+    /// A node with `crossing=*` that isn't a `highway=crossing` or signals, so it's ignored
+    UnrecognisedCrossingNode,
+    /// A street directly connecting to severances, with no footway there. It's disconnected from
+    /// the severances, assuming the crossing is mistagged or missing.
+    DisconnectedFromSeverance,
+    /// A crossing way meets a severance at a node without crossing tags
+    CrossingWayWithoutNode,
+    /// A footway crosses a severance on the same layer without sharing a node
+    MissingJunction,
+    /// A sidewalk ends just short of another path
+    DanglingSidewalk,
+    /// The crossing tags contradict each other, or the crossing node and way disagree
+    ConflictingCrossingTags,
+    /// The elevation data gives an implausibly steep gradient
+    UnusualSlope,
+}
+
+/// A tagging problem for mappers to check
+#[derive(Serialize, Deserialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// A link to the OSM object to fix
+    pub url: String,
+    /// In WGS84 while scraping, then Mercator
+    pub geometry: Geometry,
+    pub description: String,
+}
+
+impl Issue {
+    pub fn new(
+        kind: IssueKind,
+        url: String,
+        geometry: impl Into<Geometry>,
+        description: String,
+    ) -> Self {
+        Self {
+            kind,
+            url,
+            geometry: geometry.into(),
+            description,
+        }
+    }
+}
+
+/// Describes a contradiction between crossing tags, if there is one
+pub fn conflicting_crossing_tags(tags: &Tags) -> Option<String> {
+    let crossing = tags.get("crossing").map(|x| x.as_str());
+    let markings = tags.get("crossing:markings").map(|x| x.as_str());
+    if crossing == Some("traffic_signals") && tags.is("crossing:signals", "no") {
+        return Some("crossing=traffic_signals, but crossing:signals=no".to_string());
+    }
+    if tags.is("crossing:signals", "yes")
+        && matches!(crossing, Some("uncontrolled" | "unmarked" | "informal"))
+    {
+        return Some(format!(
+            "crossing:signals=yes, but crossing={}",
+            crossing.unwrap()
+        ));
+    }
+    if matches!(crossing, Some("marked" | "uncontrolled" | "zebra")) && markings == Some("no") {
+        return Some(format!(
+            "crossing={}, but crossing:markings=no",
+            crossing.unwrap()
+        ));
+    }
+    if crossing == Some("unmarked") && markings.is_some_and(|x| x != "no") {
+        return Some(format!(
+            "crossing=unmarked, but crossing:markings={}",
+            markings.unwrap()
+        ));
+    }
+    if crossing == Some("traffic_signals") && tags.is_any("crossing_ref", vec!["zebra", "tiger"]) {
+        return Some(format!(
+            "crossing=traffic_signals, but crossing_ref={}",
+            tags.get("crossing_ref").unwrap()
+        ));
+    }
+    None
+}
+
+/// Finds footways crossing a severance on the same layer without sharing a node, and sidewalks
+/// that end just short of another path
+pub fn check_graph(graph: &Graph, road_kinds: &[RoadKind]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let walkable = |r: RoadID| {
+        !matches!(
+            road_kinds[r.0],
+            RoadKind::Severance | RoadKind::Elevator | RoadKind::Escalator
+        )
+    };
+    let layer = |tags: &Tags| {
+        tags.get("layer")
+            .cloned()
+            .unwrap_or_else(|| "0".to_string())
+    };
+    let grade_separated = |tags: &Tags| {
+        ["bridge", "tunnel"]
+            .iter()
+            .any(|k| tags.has(k) && !tags.is(k, "no"))
+    };
+
+    let severances = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .filter(|r| road_kinds[r.id.0] == RoadKind::Severance)
+            .flat_map(|r| {
+                r.linestring
+                    .lines()
+                    .map(|line| GeomWithData::new(line, r.id))
+            })
+            .collect(),
+    );
+    for road in &graph.roads {
+        if !matches!(
+            road_kinds[road.id.0],
+            RoadKind::Footway | RoadKind::Crossing(_)
+        ) || grade_separated(&road.osm_tags)
+        {
+            continue;
+        }
+        let bbox = road.linestring.bounding_rect().unwrap();
+        let mut hit = None;
+        for obj in severances.locate_in_envelope_intersecting(&AABB::from_corners(
+            bbox.min().into(),
+            bbox.max().into(),
+        )) {
+            let severance = &graph.roads[obj.data.0];
+            // If they share a node, the graph is split there
+            if [road.src_i, road.dst_i]
+                .iter()
+                .any(|i| *i == severance.src_i || *i == severance.dst_i)
+                || layer(&severance.osm_tags) != layer(&road.osm_tags)
+                || grade_separated(&severance.osm_tags)
+            {
+                continue;
+            }
+            // Footways meeting a severance at the same point, but on a copy of its intersection,
+            // only touch it
+            if proper_crossing(&road.linestring, &severance.linestring).is_some() {
+                hit = Some(severance);
+                break;
+            }
+        }
+        if let Some(severance) = hit {
+            issues.push(Issue::new(
+                IssueKind::MissingJunction,
+                road.way.to_string(),
+                road.linestring.clone(),
+                format!("Crosses {} without a shared node", severance.way),
+            ));
+        }
+    }
+
+    // How many roads end at each intersection?
+    let mut degree: HashMap<IntersectionID, usize> = HashMap::new();
+    for road in &graph.roads {
+        *degree.entry(road.src_i).or_default() += 1;
+        *degree.entry(road.dst_i).or_default() += 1;
+    }
+    let paths = RTree::bulk_load(
+        graph
+            .roads
+            .iter()
+            .filter(|r| walkable(r.id))
+            .flat_map(|r| {
+                r.linestring
+                    .lines()
+                    .map(|line| GeomWithData::new(line, r.id))
+            })
+            .collect(),
+    );
+    for road in &graph.roads {
+        if road_kinds[road.id.0] != RoadKind::Footway || !road.osm_tags.is("footway", "sidewalk") {
+            continue;
+        }
+        for (i, pt) in [
+            (road.src_i, road.linestring.0[0]),
+            (road.dst_i, *road.linestring.0.last().unwrap()),
+        ] {
+            if degree[&i] != 1 {
+                continue;
+            }
+            let nearby = paths
+                .locate_within_distance(Point::from(pt), DANGLING_M * DANGLING_M)
+                .find(|obj| obj.data != road.id);
+            let Some(obj) = nearby else {
+                continue;
+            };
+            let closest = match obj.geom().closest_point(&Point::from(pt)) {
+                Closest::Intersection(pt) | Closest::SinglePoint(pt) => pt,
+                Closest::Indeterminate => continue,
+            };
+            issues.push(Issue::new(
+                IssueKind::DanglingSidewalk,
+                road.way.to_string(),
+                Line::new(pt, closest.into()),
+                format!(
+                    "Sidewalk ends just short of {}",
+                    graph.roads[obj.data.0].way
+                ),
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Reports implausible gradients from elevation data
+pub fn check_gradients(graph: &Graph, slopes: &[Slope]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for road in &graph.roads {
+        let gradient = slopes[road.id.0].max_slope;
+        if gradient > UNUSUAL_GRADIENT {
+            issues.push(Issue::new(
+                IssueKind::UnusualSlope,
+                road.way.to_string(),
+                road.linestring.clone(),
+                format!(
+                    "Unusual gradient of {gradient:.1}% somewhere along {:.0} meters",
+                    road.length_meters
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+/// Returns a GeoJSON report of all issues, each with its kind, description, and OSM link
+pub fn report(map: &MapModel) -> Result<String> {
+    let mut features = Vec::new();
+    for issue in &map.issues {
+        let mut f = map.graph.mercator.to_wgs84_gj(&issue.geometry);
+        f.set_property("kind", format!("{:?}", issue.kind));
+        f.set_property("url", issue.url.clone());
+        f.set_property("description", issue.description.clone());
+        features.push(f);
+    }
+    Ok(serde_json::to_string(&GeoJson::from(features))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_conflicting_crossing_tags() {
+        for (input, conflict) in [
+            (vec!["crossing=traffic_signals"], false),
+            (
+                vec!["crossing=traffic_signals", "crossing:signals=no"],
+                true,
+            ),
+            (
+                vec!["crossing=uncontrolled", "crossing:markings=zebra"],
+                false,
+            ),
+            (vec!["crossing=uncontrolled", "crossing:markings=no"], true),
+            (vec!["crossing=unmarked", "crossing:markings=dots"], true),
+            (
+                vec!["crossing=traffic_signals", "crossing_ref=pelican"],
+                false,
+            ),
+            (vec!["crossing=traffic_signals", "crossing_ref=zebra"], true),
+        ] {
+            assert_eq!(
+                conflicting_crossing_tags(&tags(input.clone())).is_some(),
+                conflict,
+                "{input:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use geo::{Coord, Haversine, Length, LineString, Rect};
+use geojson::FeatureCollection;
+use graph::{Graph, Timer};
+use osm_reader::{NodeID, OsmID, RelationID, WayID};
+use serde::Serialize;
+use utils::Tags;
+use utils::osm2graph::OsmReader;
+
+/// Roads where sidewalks are mapped separately (or tagged as being mapped separately) in at least
+/// this fraction of their length lean towards SeparateWays
+const SEPARATE_THRESHOLD: f64 = 0.5;
+/// Ignore zones with less road than this
+const MIN_ZONE_ROAD_M: f64 = 500.0;
+const ZONE_SIZE_M: f64 = 1000.0;
+
+/// Picks between the SeparateWays and SidewalksOnHighways profiles, by measuring how sidewalks are
+/// mapped across the area.
+pub struct Recommendation {
+    /// The name of a built-in profile, leaned towards by the most road length
+    pub profile: String,
+    /// Every zone with enough road to judge, and the profile it leans towards. This is only
+    /// reported, since a map is built with one profile throughout.
+    pub zones: Vec<ZoneRecommendation>,
+    /// One polygon per zone with the coverage stats and the zone's own recommendation. Stats per
+    /// road class over the whole area are in the foreign members.
+    pub evidence: FeatureCollection,
+}
+
+/// One square of the area, `ZONE_SIZE_M` wide
+pub struct ZoneRecommendation {
+    /// In WGS84
+    pub bounds: Rect,
+    /// The name of a built-in profile
+    pub profile: String,
+    pub road_m: f64,
+}
+
+impl Recommendation {
+    /// Some zones lean towards a different profile than the overall recommendation
+    pub fn is_mixed(&self) -> bool {
+        self.zones.iter().any(|zone| zone.profile != self.profile)
+    }
+
+    /// The fraction of road length in zones leaning towards each profile
+    pub fn mix(&self) -> BTreeMap<String, f64> {
+        let total: f64 = self.zones.iter().map(|zone| zone.road_m).sum();
+        let mut mix = BTreeMap::new();
+        for zone in &self.zones {
+            *mix.entry(zone.profile.clone()).or_insert(0.0) += zone.road_m / total;
+        }
+        mix
+    }
+}
+
+/// Parses OSM input and recommends a profile to use for it
+pub fn recommend_profile(input_bytes: &[u8]) -> Result<Recommendation> {
+    let mut coverage = SidewalkCoverage::default();
+    // The graph isn't used for routing, just to scrape ways and get a Mercator projection
+    let graph = Graph::new(
+        input_bytes,
+        &mut coverage,
+        Box::new(|_| Ok(())),
+        Box::new(|_, _| Ok(())),
+        Vec::new(),
+        &mut Timer::new("measure sidewalk coverage", None),
+    )?;
+
+    let (zones, per_class) = tally(coverage.ways.into_iter().map(|way| {
+        let start = graph.mercator.pt_to_mercator(way.first_pt);
+        let zone = (
+            (start.x / ZONE_SIZE_M).max(0.0) as usize,
+            (start.y / ZONE_SIZE_M).max(0.0) as usize,
+        );
+        (zone, way)
+    }));
+    let (profile, zone_profiles) = vote(&zones);
+
+    let mut recommendations = Vec::new();
+    let mut features = Vec::new();
+    for ((x, y), stats) in &zones {
+        let min = Coord {
+            x: (*x as f64) * ZONE_SIZE_M,
+            y: (*y as f64) * ZONE_SIZE_M,
+        };
+        let max = Coord {
+            x: ((x + 1) as f64) * ZONE_SIZE_M,
+            y: ((y + 1) as f64) * ZONE_SIZE_M,
+        };
+        let mut f = graph
+            .mercator
+            .to_wgs84_gj(&Rect::new(min, max).to_polygon());
+        stats.set_properties(&mut f);
+        if let Some(zone_profile) = zone_profiles.get(&(*x, *y)) {
+            f.set_property("recommendation", *zone_profile);
+            recommendations.push(ZoneRecommendation {
+                bounds: Rect::new(
+                    graph.mercator.pt_to_wgs84(min),
+                    graph.mercator.pt_to_wgs84(max),
+                ),
+                profile: zone_profile.to_string(),
+                road_m: stats.road_m,
+            });
+        }
+        features.push(f);
+    }
+
+    let per_class: BTreeMap<String, serde_json::Value> = per_class
+        .into_iter()
+        .map(|(class, stats)| (class, serde_json::to_value(stats).unwrap()))
+        .collect();
+    let mut recommendation = Recommendation {
+        profile,
+        zones: recommendations,
+        evidence: FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: None,
+        },
+    };
+    let mix = recommendation.mix();
+    for (profile, fraction) in &mix {
+        info!(
+            "{profile} suits zones with {:.0}% of the road length",
+            100.0 * fraction
+        );
+    }
+    recommendation.evidence.foreign_members = Some(
+        serde_json::json!({
+            "recommendation": recommendation.profile,
+            "mixed": recommendation.is_mixed(),
+            "mix": mix,
+            "per_class": per_class,
+        })
+        .as_object()
+        .unwrap()
+        .clone(),
+    );
+    Ok(recommendation)
+}
+
+/// Sums up the ways in each zone and road class
+fn tally(
+    ways: impl Iterator<Item = ((usize, usize), ScrapedWay)>,
+) -> (BTreeMap<(usize, usize), Stats>, BTreeMap<String, Stats>) {
+    let mut zones: BTreeMap<(usize, usize), Stats> = BTreeMap::new();
+    let mut per_class: BTreeMap<String, Stats> = BTreeMap::new();
+    for (zone, way) in ways {
+        zones.entry(zone).or_default().add(&way);
+        if let WayKind::Road { ref class, .. } = way.kind {
+            per_class.entry(class.clone()).or_default().add(&way);
+        }
+    }
+    (zones, per_class)
+}
+
+/// Recommends a profile for every zone with enough road, and overall. Each zone's recommendation is
+/// weighted by how much road it has.
+fn vote(
+    zones: &BTreeMap<(usize, usize), Stats>,
+) -> (String, BTreeMap<(usize, usize), &'static str>) {
+    let mut per_zone = BTreeMap::new();
+    let mut votes: BTreeMap<&'static str, f64> = BTreeMap::new();
+    for (zone, stats) in zones {
+        if stats.road_m >= MIN_ZONE_ROAD_M {
+            let profile = stats.recommend();
+            *votes.entry(profile).or_insert(0.0) += stats.road_m;
+            per_zone.insert(*zone, profile);
+        }
+    }
+    let profile = votes
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(profile, _)| profile)
+        .unwrap_or("SidewalksOnHighways");
+    (profile.to_string(), per_zone)
+}
+
+/// Total lengths in meters
+#[derive(Default, Serialize)]
+struct Stats {
+    road_m: f64,
+    /// Roads with any sidewalk tag
+    tagged_m: f64,
+    /// Roads claiming sidewalks are mapped as separate ways
+    separate_m: f64,
+    /// Ways tagged footway=sidewalk
+    sidewalk_ways_m: f64,
+}
+
+impl Stats {
+    fn add(&mut self, way: &ScrapedWay) {
+        match way.kind {
+            WayKind::Road {
+                tagged, separate, ..
+            } => {
+                self.road_m += way.length_m;
+                if tagged {
+                    self.tagged_m += way.length_m;
+                }
+                if separate {
+                    self.separate_m += way.length_m;
+                }
+            }
+            WayKind::Sidewalk => {
+                self.sidewalk_ways_m += way.length_m;
+            }
+        }
+    }
+
+    fn recommend(&self) -> &'static str {
+        // Separately mapped sidewalks usually exist on both sides of a road, so they count half
+        let separate_share =
+            (self.separate_m / self.road_m).max(0.5 * self.sidewalk_ways_m / self.road_m);
+        if separate_share >= SEPARATE_THRESHOLD {
+            "SeparateWays"
+        } else {
+            "SidewalksOnHighways"
+        }
+    }
+
+    fn set_properties(&self, f: &mut geojson::Feature) {
+        f.set_property("road_length", self.road_m);
+        f.set_property("sidewalk_ways_length", self.sidewalk_ways_m);
+        if self.road_m > 0.0 {
+            f.set_property("pct_tagged", 100.0 * self.tagged_m / self.road_m);
+            f.set_property("pct_separate", 100.0 * self.separate_m / self.road_m);
+        }
+    }
+}
+
+#[derive(Default)]
+struct SidewalkCoverage {
+    ways: Vec<ScrapedWay>,
+}
+
+struct ScrapedWay {
+    kind: WayKind,
+    first_pt: Coord,
+    length_m: f64,
+}
+
+enum WayKind {
+    Road {
+        /// highway=*, ignoring the _link suffix
+        class: String,
+        tagged: bool,
+        separate: bool,
+    },
+    Sidewalk,
+}
+
+impl OsmReader for SidewalkCoverage {
+    fn node(&mut self, _: NodeID, _: Coord, _: Tags) {}
+
+    fn way(
+        &mut self,
+        _: WayID,
+        node_ids: &Vec<NodeID>,
+        node_mapping: &HashMap<NodeID, Coord>,
+        tags: &Tags,
+    ) {
+        let Some(highway) = tags.get("highway") else {
+            return;
+        };
+        let kind = if tags.is("footway", "sidewalk") {
+            WayKind::Sidewalk
+        } else if [
+            "primary",
+            "secondary",
+            "tertiary",
+            "residential",
+            "unclassified",
+            "living_street",
+        ]
+        .contains(&highway.trim_end_matches("_link"))
+        {
+            let keys = [
+                "sidewalk",
+                "sidewalk:both",
+                "sidewalk:left",
+                "sidewalk:right",
+            ];
+            WayKind::Road {
+                class: highway.trim_end_matches("_link").to_string(),
+                tagged: keys.iter().any(|k| tags.has(k)),
+                separate: keys.iter().any(|k| tags.is(k, "separate")),
+            }
+        } else {
+            return;
+        };
+
+        let linestring = LineString::new(node_ids.iter().map(|id| node_mapping[id]).collect());
+        self.ways.push(ScrapedWay {
+            kind,
+            first_pt: node_mapping[&node_ids[0]],
+            length_m: Haversine.length(&linestring),
+        });
+    }
+
+    fn relation(&mut self, _: RelationID, _: &Vec<(String, OsmID)>, _: &Tags) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn road(tags: &str, length_m: f64) -> ScrapedWay {
+        ScrapedWay {
+            kind: WayKind::Road {
+                class: "residential".to_string(),
+                tagged: !tags.is_empty(),
+                separate: tags == "separate",
+            },
+            first_pt: Coord { x: 0.0, y: 0.0 },
+            length_m,
+        }
+    }
+
+    fn sidewalk(length_m: f64) -> ScrapedWay {
+        ScrapedWay {
+            kind: WayKind::Sidewalk,
+            first_pt: Coord { x: 0.0, y: 0.0 },
+            length_m,
+        }
+    }
+
+    #[test]
+    fn test_vote_per_zone() {
+        let (zones, per_class) = tally(
+            vec![
+                // Sidewalks tagged on the roads
+                ((0, 0), road("both", 600.0)),
+                ((0, 0), road("", 200.0)),
+                // Roads pointing at separate sidewalks
+                ((1, 0), road("separate", 400.0)),
+                ((1, 0), road("", 200.0)),
+                // Untagged roads with sidewalk ways drawn on both sides
+                ((2, 0), road("", 1000.0)),
+                ((2, 0), sidewalk(1800.0)),
+                // Too little road to judge
+                ((3, 0), road("separate", 100.0)),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(per_class["residential"].road_m, 2500.0);
+        assert_eq!(per_class["residential"].separate_m, 500.0);
+
+        let (profile, per_zone) = vote(&zones);
+        assert_eq!(per_zone[&(0, 0)], "SidewalksOnHighways");
+        assert_eq!(per_zone[&(1, 0)], "SeparateWays");
+        assert_eq!(per_zone[&(2, 0)], "SeparateWays");
+        assert!(!per_zone.contains_key(&(3, 0)));
+        // 1600m of road in zones leaning towards SeparateWays, against 800m
+        assert_eq!(profile, "SeparateWays");
+    }
+
+    #[test]
+    fn test_mix() {
+        let zone = |x: f64, profile: &str, road_m: f64| ZoneRecommendation {
+            bounds: Rect::new(Coord { x, y: 0.0 }, Coord { x: x + 1.0, y: 1.0 }),
+            profile: profile.to_string(),
+            road_m,
+        };
+        let recommendation = Recommendation {
+            profile: "SeparateWays".to_string(),
+            zones: vec![
+                zone(0.0, "SeparateWays", 3000.0),
+                zone(1.0, "SidewalksOnHighways", 1000.0),
+            ],
+            evidence: FeatureCollection {
+                features: Vec::new(),
+                bbox: None,
+                foreign_members: None,
+            },
+        };
+        assert!(recommendation.is_mixed());
+        assert_eq!(recommendation.mix()["SeparateWays"], 0.75);
+        assert_eq!(recommendation.mix()["SidewalksOnHighways"], 0.25);
+    }
+}
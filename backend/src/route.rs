@@ -1,13 +1,17 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use geo::{Coord, Euclidean, Length, LineString};
 use geojson::{Feature, FeatureCollection, Geometry};
-use graph::{Direction, PathStep, ProfileID};
+use graph::{PathStep, Position, ProfileID, RoadID, Route};
 use itertools::Itertools;
 use serde::Serialize;
 
-use crate::{MapModel, Settings, cost};
+use crate::signals::{SignalTiming, signal_wait};
+use crate::{
+    Departure, MapModel, PROHIBITIVE_COST, RoadKind, Settings, StepFree, cost, routing_cost,
+};
 
 // Also returns the line of the snapped request (in WGS84)
 pub fn do_route(
@@ -15,29 +19,67 @@ pub fn do_route(
     start: Coord,
     end: Coord,
     settings: Settings,
+    departure: Departure,
 ) -> Result<(Feature, FeatureCollection)> {
-    let profile = map.prepare_profile(settings.clone());
+    let time_band = settings.band_at(departure).map(|band| band.name.clone());
+    let settings = settings.at(departure);
+    let profile = map.prepare_profile(settings.clone(), departure);
+    let closed = map.closed_roads(departure);
     let start = map.graph.snap_to_road(start, profile);
     let end = map.graph.snap_to_road(end, profile);
 
-    let route = map.graph.routers[profile.0].route(&map.graph, start, end)?;
+    let route = map.directed_route(start, end, profile)?;
     let route_linestring = route.linestring(&map.graph);
 
     let mut active_duration = Duration::ZERO;
     let mut waiting_duration = Duration::ZERO;
     let mut directions = Vec::new();
+    // For the comfort along the route, weighted by length
+    let mut comfort_meters = 0.0;
+    let mut total_meters = 0.0;
+    let mut max_traffic_stress = 1;
+    let mut route_roads = HashSet::new();
     for (pos, step) in route.steps.into_iter().with_position() {
         if let PathStep::Road { road, forwards } = step {
+            route_roads.insert(road);
             let r = &map.graph.roads[road.0];
+            let (cost1, cost2) = cost(
+                &r.linestring,
+                map.road_kinds[road.0],
+                map.slopes[road.0].directed(forwards),
+                map.crossing_shares[road.0],
+                &settings,
+            );
+            let (crossing_wait_s, signal_timing) = match map.road_kinds[road.0] {
+                RoadKind::Crossing(kind) => (
+                    Some(cost2.as_secs_f64()),
+                    signal_wait(kind, map.crossing_shares[road.0].timing, &settings)
+                        .map(|(_, timing)| timing),
+                ),
+                _ => (None, None),
+            };
             directions.push(Step {
                 name: r.osm_tags.get("name").cloned(),
                 way: r.way.to_string(),
                 kind: format!("{:?}", map.road_kinds[road.0]),
+                access: format!("{:?}", map.access[road.0]),
+                // These only happen when there's no other way
+                closed: closed.contains(&road),
+                unusable: match map.step_free[road.0] {
+                    StepFree::Unusable(ref reason) if settings.step_free => Some(reason.clone()),
+                    _ => None,
+                },
                 layer: r
                     .osm_tags
                     .get("layer")
                     .cloned()
                     .unwrap_or_else(|| "0".to_string()),
+                level: r.osm_tags.get("level").cloned(),
+                crossing_wait_s,
+                signal_timing,
+                comfort: map.comfort[road.0].score,
+                traffic_stress: map.comfort[road.0].traffic_stress,
+                comfort_reasons: map.comfort[road.0].describe_reasons(),
             });
 
             let percent = match pos {
@@ -61,17 +103,35 @@ pub fn do_route(
                 }
             };
 
-            let (cost1, cost2) = cost(
-                &r.linestring,
-                map.road_kinds[road.0],
-                map.gradients[road.0],
-                &settings,
-            );
             active_duration += cost1.mul_f64(percent);
             waiting_duration += cost2;
+            let length = r.length_meters * percent;
+            comfort_meters += map.comfort[road.0].score * length;
+            total_meters += length;
+            max_traffic_stress = max_traffic_stress.max(map.comfort[road.0].traffic_stress);
         }
     }
 
+    // Explain roads next to the route that were avoided
+    let mut avoided = BTreeMap::new();
+    for r in &closed {
+        let hours = map.opening_hours[r]
+            .iter()
+            .map(|oh| oh.raw.as_str())
+            .join("; ");
+        avoided.insert(*r, format!("closed at this time, opening_hours={hours}"));
+    }
+    if settings.step_free {
+        for (idx, step_free) in map.step_free.iter().enumerate() {
+            if let StepFree::Unusable(reason) = step_free {
+                avoided
+                    .entry(RoadID(idx))
+                    .or_insert_with(|| format!("not step-free, {reason}"));
+            }
+        }
+    }
+    let avoided_nearby = avoided_nearby(map, &avoided, &route_roads);
+
     // TODO More exactly, the point snapped to the road?
     let direct_line = LineString::new(vec![
         map.graph.intersections[start.intersection.0].point.into(),
@@ -92,6 +152,12 @@ pub fn do_route(
                     "directions": directions,
                     "active_duration_s": active_duration.as_secs(),
                     "waiting_duration_s": waiting_duration.as_secs(),
+                    "departure": departure,
+                    "time_band": time_band,
+                    "signal_model": settings.signal_model,
+                    "avoided_nearby": avoided_nearby,
+                    "comfort": if total_meters > 0.0 { comfort_meters / total_meters } else { 1.0 },
+                    "max_traffic_stress": max_traffic_stress,
                 })
                 .as_object()
                 .unwrap()
@@ -106,43 +172,159 @@ struct Step {
     name: Option<String>,
     way: String,
     kind: String,
+    access: String,
+    closed: bool,
+    /// Why the road isn't step-free, in that mode
+    unusable: Option<String>,
     layer: String,
+    /// Indoors, `level=*`. Elevators and escalators list every level they connect.
+    level: Option<String>,
+    /// Seconds waiting on this part of a crossing
+    crossing_wait_s: Option<f64>,
+    /// The timing used for a signalized crossing, unless the signal model is flat
+    signal_timing: Option<SignalTiming>,
+    /// From 0 to 1
+    comfort: f64,
+    traffic_stress: usize,
+    comfort_reasons: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct AvoidedRoad {
+    name: Option<String>,
+    way: String,
+    reason: String,
+}
+
+/// Avoided roads touching the route, which might've been used otherwise
+fn avoided_nearby(
+    map: &MapModel,
+    avoided: &BTreeMap<RoadID, String>,
+    route_roads: &HashSet<RoadID>,
+) -> Vec<AvoidedRoad> {
+    let mut intersections = HashSet::new();
+    for r in route_roads {
+        let road = &map.graph.roads[r.0];
+        intersections.insert(road.src_i);
+        intersections.insert(road.dst_i);
+    }
+
+    let mut output = Vec::new();
+    for (r, reason) in avoided {
+        let road = &map.graph.roads[r.0];
+        if route_roads.contains(r)
+            || !(intersections.contains(&road.src_i) || intersections.contains(&road.dst_i))
+        {
+            continue;
+        }
+        output.push(AvoidedRoad {
+            name: road.osm_tags.get("name").cloned(),
+            way: road.way.to_string(),
+            reason: reason.clone(),
+        });
+    }
+    output
 }
 
 impl MapModel {
-    pub fn prepare_profile(&mut self, settings: Settings) -> ProfileID {
+    pub fn prepare_profile(&mut self, settings: Settings, departure: Departure) -> ProfileID {
+        let settings = settings.at(departure);
         let profile_name = if settings.obey_crossings {
             "walking"
         } else {
             "cross_anywhere"
         };
         let profile = self.graph.profile_names[profile_name];
+        let closed = self.closed_roads(departure);
 
-        // TODO This is getting called upfront after creation; f64 comparisons?
-        if (settings.obey_crossings && self.walking_settings != settings)
-            || (!settings.obey_crossings && self.cross_anywhere_settings != settings)
-        {
-            info!("Updating costs for {profile_name}");
-
-            for road in &mut self.graph.roads {
-                if road.access[profile.0] == Direction::Both {
-                    let (cost1, cost2) = cost(
-                        &road.linestring,
-                        self.road_kinds[road.id.0],
-                        self.gradients[road.id.0],
-                        &settings,
-                    );
-                    road.cost[profile.0] = cost1 + cost2;
-                }
-            }
-            self.graph.routers[profile.0].update_costs(&self.graph.roads, profile);
-            if settings.obey_crossings {
-                self.walking_settings = settings;
-            } else {
-                self.cross_anywhere_settings = settings;
-            }
+        // Routes and isochrones calculate each road's cost from these as they search, so there's
+        // nothing else to update
+        if settings.obey_crossings {
+            self.walking_settings = settings;
+            self.walking_closed = closed;
+        } else {
+            self.cross_anywhere_settings = settings;
+            self.cross_anywhere_closed = closed;
         }
 
         profile
     }
+
+    /// The routing cost of walking a road from `src_i` to `dst_i`, or backwards
+    pub(crate) fn directed_cost(
+        &self,
+        r: RoadID,
+        forwards: bool,
+        settings: &Settings,
+        closed: &BTreeSet<RoadID>,
+    ) -> Duration {
+        if closed.contains(&r) {
+            return PROHIBITIVE_COST;
+        }
+        routing_cost(
+            &self.graph.roads[r.0].linestring,
+            self.road_kinds[r.0],
+            self.slopes[r.0].directed(forwards),
+            self.crossing_shares[r.0],
+            self.access[r.0],
+            &self.step_free[r.0],
+            self.comfort[r.0].score,
+            settings,
+        )
+    }
+
+    /// Finds the fastest route for a prepared profile, walking each road with its cost in that
+    /// direction, so a route can go up one way and come back down another
+    pub(crate) fn directed_route(
+        &self,
+        start: Position,
+        end: Position,
+        profile: ProfileID,
+    ) -> Result<Route> {
+        if start.road == end.road {
+            if start.fraction_along == end.fraction_along {
+                bail!("start = end");
+            }
+            return Ok(Route {
+                steps: vec![PathStep::Road {
+                    road: start.road,
+                    forwards: start.fraction_along < end.fraction_along,
+                }],
+                start,
+                end,
+            });
+        }
+
+        let search = self.directed_search(
+            start.intersection,
+            profile,
+            Duration::MAX,
+            Some(end.intersection),
+            &[start.road, end.road],
+        );
+        let mut steps = Vec::new();
+        let mut i = end.intersection;
+        while i != start.intersection {
+            let Some((road, forwards)) = search.reached_by.get(&i) else {
+                bail!("No path");
+            };
+            steps.push(PathStep::Road {
+                road: *road,
+                forwards: *forwards,
+            });
+            let road = &self.graph.roads[road.0];
+            i = if *forwards { road.src_i } else { road.dst_i };
+        }
+        steps.reverse();
+        Ok(Route { start, end, steps })
+    }
+
+    /// The settings and closed roads last used to prepare a profile
+    pub(crate) fn prepared(&self, profile: ProfileID) -> (&Settings, &BTreeSet<RoadID>) {
+        if profile == self.graph.profile_names["walking"] {
+            (&self.walking_settings, &self.walking_closed)
+        } else {
+            (&self.cross_anywhere_settings, &self.cross_anywhere_closed)
+        }
+    }
 }
@@ -6,14 +6,23 @@ use geojson::{FeatureCollection, GeoJson};
 use graph::RoadID;
 use utils::{KeyedLineString, LineSplit, collapse_degree_2};
 
-use crate::{Crossing, MapModel, RoadKind, Settings};
+use crate::{Crossing, Departure, MapModel, RoadKind, Settings};
 
 // Walk along severances. Every X meters, try to cross from one side to the other.
 //
 // We could focus where footways connect to severances, but that's probably a crossing. Ideally we
 // want to find footpaths parallel(ish) to severances. If we had some kind of generalized edge
 // bundling...
-pub fn calculate(map: &mut MapModel, settings: Settings) -> FeatureCollection {
+pub fn calculate(
+    map: &mut MapModel,
+    settings: Settings,
+    departures: &[Departure],
+) -> FeatureCollection {
+    let departures = if departures.is_empty() {
+        vec![Departure::default()]
+    } else {
+        departures.to_vec()
+    };
     let mut requests = Vec::new();
     for r in &map.graph.roads {
         if map.road_kinds[r.id.0] == RoadKind::Severance {
@@ -23,36 +32,50 @@ pub fn calculate(map: &mut MapModel, settings: Settings) -> FeatureCollection {
         }
     }
 
-    let mut samples = Vec::new();
-    let mut max_score = 0.0_f64;
-    for (start, end) in requests {
-        if let Ok((mut f, fc)) = crate::route::do_route(map, start, end, settings.clone()) {
-            let direct = fc
-                .foreign_members
-                .as_ref()
-                .unwrap()
-                .get("direct_length")
-                .unwrap()
-                .as_f64()
-                .unwrap();
-            let route = fc
-                .foreign_members
-                .unwrap()
-                .get("route_length")
-                .unwrap()
-                .as_f64()
-                .unwrap();
-            let score = route / direct;
-            max_score = max_score.max(score);
-            f.set_property("score", score);
-            samples.push(f);
+    // Score every request at one departure before moving to the next, so costs only change once
+    // per departure. The first departure decides which samples to keep, and later ones can be
+    // compared to it.
+    let mut samples: Vec<Option<geojson::Feature>> = vec![None; requests.len()];
+    let mut scores: Vec<Vec<Option<f64>>> = vec![Vec::new(); requests.len()];
+    for (idx, departure) in departures.iter().enumerate() {
+        for (req, (start, end)) in requests.iter().enumerate() {
+            let Ok((f, fc)) =
+                crate::route::do_route(map, *start, *end, settings.clone(), *departure)
+            else {
+                scores[req].push(None);
+                continue;
+            };
+            let info = fc.foreign_members.unwrap();
+            let direct = info["direct_length"].as_f64().unwrap();
+            let route = info["route_length"].as_f64().unwrap();
+            scores[req].push(Some(route / direct));
+            if idx == 0 {
+                samples[req] = Some(f);
+            }
         }
     }
+
+    let mut features = Vec::new();
+    let mut max_score = 0.0_f64;
+    for (sample, scores) in samples.into_iter().zip(scores) {
+        let (Some(mut f), Some(score)) = (sample, scores[0]) else {
+            continue;
+        };
+        max_score = max_score.max(score);
+        f.set_property("score", score);
+        f.set_property("scores", scores);
+        features.push(f);
+    }
     info!("Max score is {max_score}");
     FeatureCollection {
-        features: samples,
+        features,
         bbox: None,
-        foreign_members: None,
+        foreign_members: Some(
+            serde_json::json!({ "departures": departures })
+                .as_object()
+                .unwrap()
+                .clone(),
+        ),
     }
 }
 
@@ -103,10 +126,7 @@ pub fn get_crossing_distances(map: &MapModel, include_kinds: HashSet<String>) ->
         joined_lines,
         map.crossings
             .iter()
-            .filter(|c| match c.tags.get("crossing") {
-                Some(kind) => include_kinds.contains(kind),
-                None => include_kinds.contains("unknown"),
-            })
+            .filter(|c| include_kinds.contains(c.filter_key()))
             .collect(),
     );
 
@@ -130,8 +150,10 @@ fn split_by_crossings(
 
         // Even if there are no crossings on this road, never drop any input
         let mut fractions = vec![0.0, 1.0];
+        // Only split once per logical crossing, even if several crossing nodes are on this line
+        let mut clusters = HashSet::new();
         for crossing in &crossings {
-            if crossing.roads.is_disjoint(&roads) {
+            if crossing.roads.is_disjoint(&roads) || !clusters.insert(crossing.cluster) {
                 continue;
             }
             let Some(fraction) = joined_line
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use geo::Point;
+use utils::osm2graph::{EdgeID, Graph, IntersectionID};
+
+use crate::create::copy_intersection;
+use crate::qa::{Issue, IssueKind};
+use crate::{Profile, RoadKind};
+
+// Side streets often join a severance at the same node as a street on the other side, with no
+// crossing mapped there. Since WithTraffic streets are walkable, routing would cross the severance
+// at that node for free. The severance edges at an intersection divide the space around it into
+// sides. When walkable edges leave on more than one side and nothing there says people cross, give
+// each side its own intersection, so the severance has to be crossed somewhere else.
+//
+// Crossings, pedestrian areas, elevators, and escalators at the intersection mean people do cross
+// there. Footways leaving on more than one side are assumed to be a mistagged crossing.
+pub fn disconnect_side_streets(graph: &mut Graph, profile: &Profile) -> Vec<Issue> {
+    let mut splits: Vec<(IntersectionID, Vec<Vec<EdgeID>>, String)> = Vec::new();
+    for intersection in graph.intersections.values() {
+        let mut severance_bearings = Vec::new();
+        let mut others = Vec::new();
+        let mut crossable = false;
+        for e in &intersection.edges {
+            let Some(kind) = profile.classify(&graph.edges[e].osm_tags) else {
+                continue;
+            };
+            let bearing = leaving_bearing(graph, intersection.id, *e);
+            match kind {
+                RoadKind::Severance => severance_bearings.push(bearing),
+                RoadKind::WithTraffic | RoadKind::Footway => others.push((*e, kind, bearing)),
+                RoadKind::Crossing(_)
+                | RoadKind::PedestrianArea
+                | RoadKind::Elevator
+                | RoadKind::Escalator => crossable = true,
+            }
+        }
+        // If the severance ends here, there's nothing to cross
+        if crossable || severance_bearings.len() < 2 || others.is_empty() {
+            continue;
+        }
+
+        let mut sides: BTreeMap<usize, Vec<EdgeID>> = BTreeMap::new();
+        let mut footway_sides: BTreeSet<usize> = BTreeSet::new();
+        for (e, kind, bearing) in others {
+            let side = side_of(&severance_bearings, bearing);
+            sides.entry(side).or_default().push(e);
+            if kind == RoadKind::Footway {
+                footway_sides.insert(side);
+            }
+        }
+        if sides.len() < 2 || footway_sides.len() > 1 {
+            continue;
+        }
+        let reason = format!(
+            "{} severance edges meet here, with streets leaving on {} different sides and no \
+             crossing",
+            severance_bearings.len(),
+            sides.len()
+        );
+        splits.push((intersection.id, sides.into_values().collect(), reason));
+    }
+    if splits.is_empty() {
+        return Vec::new();
+    }
+    info!(
+        "Disconnecting side streets from severances at {} intersections",
+        splits.len()
+    );
+
+    let mut issues = Vec::new();
+    for (i, sides, reason) in splits {
+        let point = graph.intersections[&i].point;
+        issues.push(Issue::new(
+            IssueKind::DisconnectedFromSeverance,
+            graph.intersections[&i].osm_node.to_string(),
+            point,
+            format!(
+                "{reason}, so split into {} intersections. Is a crossing mistagged or missing?",
+                sides.len() + 1
+            ),
+        ));
+
+        // The severances keep the original intersection
+        for side in sides {
+            let copy = copy_intersection(graph, i, point.into());
+            graph
+                .intersections
+                .get_mut(&i)
+                .unwrap()
+                .edges
+                .retain(|e| !side.contains(e));
+            for e in &side {
+                let edge = graph.edges.get_mut(e).unwrap();
+                if edge.src == i {
+                    edge.src = copy;
+                }
+                if edge.dst == i {
+                    edge.dst = copy;
+                }
+            }
+            graph.intersections.get_mut(&copy).unwrap().edges = side;
+        }
+    }
+    issues
+}
+
+/// The direction an edge leaves an intersection, in radians
+fn leaving_bearing(graph: &Graph, i: IntersectionID, e: EdgeID) -> f64 {
+    let edge = &graph.edges[&e];
+    let start = graph.intersections[&i].point.0;
+    let next = if edge.src == i {
+        edge.linestring.coords().find(|pt| **pt != start)
+    } else {
+        edge.linestring.coords().rev().find(|pt| **pt != start)
+    };
+    let next = next.cloned().unwrap_or(start);
+    bearing(Point::from(start), Point::from(next))
+}
+
+fn bearing(from: Point, to: Point) -> f64 {
+    (to.y() - from.y()).atan2(to.x() - from.x())
+}
+
+/// Severances leaving an intersection divide the space around it into sides. Which side is a
+/// bearing on? Sides are numbered by the severance bearing that starts them, counter-clockwise.
+fn side_of(severance_bearings: &[f64], bearing: f64) -> usize {
+    let mut sorted = severance_bearings.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    // The side starting at the last severance wraps around to the first
+    sorted
+        .iter()
+        .rposition(|b| *b <= bearing)
+        .unwrap_or(sorted.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_side_of() {
+        // A severance running east-west
+        let severance = vec![0.0, PI];
+        // North and south are on different sides
+        assert_ne!(
+            side_of(&severance, PI / 2.0),
+            side_of(&severance, -PI / 2.0)
+        );
+        // Two streets to the north are on the same side
+        assert_eq!(side_of(&severance, 0.1), side_of(&severance, PI - 0.1));
+        // The same side from either end of the range
+        assert_eq!(side_of(&severance, -0.1), side_of(&severance, -PI + 0.1));
+
+        // A severance bending at the intersection, with three legs
+        let severance = vec![0.0, PI / 2.0, -PI / 2.0];
+        assert_eq!(side_of(&severance, PI), 2);
+        assert_eq!(side_of(&severance, -PI), 2);
+        assert_eq!(side_of(&severance, -PI / 4.0), 0);
+        assert_eq!(side_of(&severance, PI / 4.0), 1);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use geo::{Coord, LineString};
+use utils::Tags;
+use utils::osm2graph::{EdgeID, Graph, IntersectionID};
+
+use crate::comfort::traffic_stress;
+use crate::create::{add_edge, copy_intersection};
+use crate::{Profile, RoadKind};
+
+// Roads with sidewalks tagged on them become one Footway edge per side, offset from the
+// centerline. Moving between the sides is only possible at the ends of a road, through an explicit
+// crossing edge.
+//
+// At every intersection touching one of these roads, the edges leaving the intersection are
+// sorted by angle, and there's a new "corner" intersection between each consecutive pair. A split
+// road's sidewalks end at the corners on either side of it, and a crossing connects those two
+// corners. Every other edge gets its own new endpoint, connected to the corners on either side of
+// it. The original intersection is removed.
+//
+// This assumes Mercator's Y axis points south, to decide which side is left.
+pub fn split_sidewalks(graph: &mut Graph, profile: &Profile) {
+    let mut expand: BTreeMap<EdgeID, Sides> = BTreeMap::new();
+    for edge in graph.edges.values() {
+        // Skip loops; the sides are ambiguous
+        if edge.src == edge.dst || profile.classify(&edge.osm_tags) != Some(RoadKind::WithTraffic) {
+            continue;
+        }
+        if let Some(sides) = Sides::from_tags(&edge.osm_tags) {
+            expand.insert(edge.id, sides);
+        }
+    }
+    if expand.is_empty() {
+        return;
+    }
+    info!("Splitting {} roads into separate sidewalks", expand.len());
+
+    let touched: BTreeSet<IntersectionID> = expand
+        .keys()
+        .flat_map(|e| [graph.edges[e].src, graph.edges[e].dst])
+        .collect();
+
+    // For each end of a split road, the corners on the side with a decreasing and increasing angle
+    let mut corners_at_end: HashMap<(EdgeID, bool), (IntersectionID, IntersectionID)> =
+        HashMap::new();
+    let mut corner_points: HashMap<IntersectionID, Coord> = HashMap::new();
+
+    for i in touched {
+        let arms = Arm::all(graph, i);
+        let center = arms[0].endpoint;
+        let width = arms
+            .iter()
+            .filter(|arm| expand.contains_key(&arm.edge))
+            .map(|arm| offset_distance(&graph.edges[&arm.edge].osm_tags))
+            .fold(0.0, f64::max);
+
+        // corners[k] is between arms k and k+1
+        let mut corners = Vec::new();
+        for (k, arm) in arms.iter().enumerate() {
+            let next = &arms[(k + 1) % arms.len()];
+            let pt = corner_point(center, arm.dir, next.dir, width);
+            let corner = copy_intersection(graph, i, pt);
+            corner_points.insert(corner, pt);
+            corners.push(corner);
+        }
+
+        // If there's a real crossing way here, the delay comes from that; don't count it twice
+        let real_crossing = arms.iter().any(|arm| {
+            !expand.contains_key(&arm.edge)
+                && matches!(
+                    profile.classify(&graph.edges[&arm.edge].osm_tags),
+                    Some(RoadKind::Crossing(_))
+                )
+        });
+        // Ways are often split where nothing else happens; don't allow crossing there
+        let continuation = arms.len() == 2 && arms.iter().all(|a| expand.contains_key(&a.edge));
+
+        let mut crossed: HashSet<(IntersectionID, IntersectionID)> = HashSet::new();
+        for (k, arm) in arms.iter().enumerate() {
+            let dec_corner = corners[(k + arms.len() - 1) % arms.len()];
+            let inc_corner = corners[k];
+            let template = graph.edges[&arm.edge].clone();
+
+            if expand.contains_key(&arm.edge) {
+                corners_at_end.insert((arm.edge, arm.at_src), (dec_corner, inc_corner));
+
+                if dec_corner == inc_corner
+                    || continuation
+                    || !crossed.insert((dec_corner.min(inc_corner), dec_corner.max(inc_corner)))
+                {
+                    continue;
+                }
+                let tags = if real_crossing {
+                    footway_tags()
+                } else {
+                    implicit_crossing_tags(&template.osm_tags, profile.country.as_deref())
+                };
+                let linestring =
+                    LineString::new(vec![corner_points[&dec_corner], corner_points[&inc_corner]]);
+                add_edge(
+                    graph,
+                    template.osm_way,
+                    dec_corner,
+                    inc_corner,
+                    linestring,
+                    tags,
+                );
+            } else {
+                // Give the edge its own endpoint, then connect that to the corners on both sides
+                let hub = copy_intersection(graph, i, arm.endpoint);
+                let edge = graph.edges.get_mut(&arm.edge).unwrap();
+                if arm.at_src {
+                    edge.src = hub;
+                } else {
+                    edge.dst = hub;
+                }
+                graph
+                    .intersections
+                    .get_mut(&hub)
+                    .unwrap()
+                    .edges
+                    .push(arm.edge);
+
+                // Walking between the corners through the hub would cross severances (and excluded
+                // edges) for free, so connect those to only one corner
+                let corners = match profile.classify(&template.osm_tags) {
+                    Some(RoadKind::Severance) | None => vec![dec_corner],
+                    _ => [dec_corner, inc_corner]
+                        .into_iter()
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect(),
+                };
+                for corner in corners {
+                    let linestring = LineString::new(vec![arm.endpoint, corner_points[&corner]]);
+                    add_edge(
+                        graph,
+                        template.osm_way,
+                        hub,
+                        corner,
+                        linestring,
+                        footway_tags(),
+                    );
+                }
+            }
+        }
+
+        graph.intersections.remove(&i);
+    }
+
+    for (e, sides) in expand {
+        let template = graph.edges.remove(&e).unwrap();
+        let (src_dec, src_inc) = corners_at_end[&(e, true)];
+        let (dst_dec, dst_inc) = corners_at_end[&(e, false)];
+        let width = offset_distance(&template.osm_tags);
+
+        let mut tags = template.osm_tags.clone();
+        tags.insert("highway", "footway");
+        tags.insert("footway", "sidewalk");
+
+        // See the Arm directions: the left side at the start of the road is on the side with a
+        // decreasing angle, but at the end of the road, it's on the increasing side.
+        for (enabled, left, src, dst) in [
+            (sides.left, true, src_dec, dst_inc),
+            (sides.right, false, src_inc, dst_dec),
+        ] {
+            if !enabled {
+                continue;
+            }
+            let mut linestring = offset_linestring(&template.linestring, width, left);
+            linestring.0[0] = corner_points[&src];
+            *linestring.0.last_mut().unwrap() = corner_points[&dst];
+            add_edge(graph, template.osm_way, src, dst, linestring, tags.clone());
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sides {
+    left: bool,
+    right: bool,
+}
+
+impl Sides {
+    fn from_tags(tags: &Tags) -> Option<Self> {
+        let both = tags.is("sidewalk", "both") || tags.is("sidewalk:both", "yes");
+        let left = both || tags.is("sidewalk", "left") || tags.is("sidewalk:left", "yes");
+        let right = both || tags.is("sidewalk", "right") || tags.is("sidewalk:right", "yes");
+        (left || right).then_some(Self { left, right })
+    }
+}
+
+/// One edge leaving an intersection
+struct Arm {
+    edge: EdgeID,
+    /// Is the intersection the edge's src or dst?
+    at_src: bool,
+    /// Where the edge touches the intersection
+    endpoint: Coord,
+    /// A unit vector pointing away from the intersection
+    dir: Coord,
+    angle: f64,
+}
+
+impl Arm {
+    /// Sorted by angle. A loop edge shows up twice.
+    fn all(graph: &Graph, i: IntersectionID) -> Vec<Self> {
+        let mut arms = Vec::new();
+        for e in &graph.intersections[&i].edges {
+            let edge = &graph.edges[e];
+            let pts = &edge.linestring.0;
+            for at_src in [true, false] {
+                if (at_src && edge.src != i) || (!at_src && edge.dst != i) {
+                    continue;
+                }
+                let (endpoint, toward) = if at_src {
+                    (pts[0], pts[1])
+                } else {
+                    (pts[pts.len() - 1], pts[pts.len() - 2])
+                };
+                let dir = unit(toward - endpoint);
+                arms.push(Arm {
+                    edge: *e,
+                    at_src,
+                    endpoint,
+                    dir,
+                    angle: dir.y.atan2(dir.x),
+                });
+            }
+        }
+        arms.sort_by(|a, b| a.angle.total_cmp(&b.angle));
+        arms
+    }
+}
+
+fn footway_tags() -> Tags {
+    let mut tags = Tags::empty();
+    tags.insert("highway", "footway");
+    tags
+}
+
+/// Tags for walking between the sidewalks at the end of a road without a real crossing. Calm roads
+/// are crossed freely, and busier ones are harder to cross the busier they are.
+fn implicit_crossing_tags(road: &Tags, country: Option<&str>) -> Tags {
+    let mut tags = footway_tags();
+    match traffic_stress(road, country) {
+        1 => {}
+        2 => {
+            tags.insert("footway", "crossing");
+            tags.insert("crossing", "unmarked");
+        }
+        _ => {
+            tags.insert("footway", "crossing");
+            tags.insert("crossing", "informal");
+        }
+    }
+    tags
+}
+
+/// Where the sidewalks along two consecutive arms of an intersection meet, `width` from both arms'
+/// centerlines. Arms are given as unit vectors pointing away from `center`.
+fn corner_point(center: Coord, arm_dir: Coord, next_dir: Coord, width: f64) -> Coord {
+    let side = perp_inc(arm_dir);
+    let dir = unit(side + perp_dec(next_dir));
+    if dir == (Coord { x: 0.0, y: 0.0 }) {
+        // A dead-end, or two edges leaving in the same direction
+        return center - arm_dir * width;
+    }
+    // Far enough along the bisector to be `width` from the arm, but not too far for sharp angles
+    let cos = dir.x * side.x + dir.y * side.y;
+    let distance = if cos > 0.0 {
+        (width / cos).min(3.0 * width)
+    } else {
+        width
+    };
+    center + dir * distance
+}
+
+/// How far from the centerline to put sidewalks, in meters
+fn offset_distance(tags: &Tags) -> f64 {
+    let road_width = tags
+        .get("width")
+        .and_then(|x| x.trim_end_matches(" m").parse::<f64>().ok())
+        .unwrap_or_else(|| {
+            let lanes = tags
+                .get("lanes")
+                .and_then(|x| x.parse::<f64>().ok())
+                .unwrap_or(2.0);
+            3.0 * lanes
+        });
+    road_width / 2.0 + 1.0
+}
+
+fn offset_linestring(linestring: &LineString, distance: f64, left: bool) -> LineString {
+    let pts = &linestring.0;
+    let mut output = Vec::new();
+    for (idx, pt) in pts.iter().enumerate() {
+        // Average the direction of the segments before and after each point
+        let prev = pts[idx.saturating_sub(1)];
+        let next = pts[(idx + 1).min(pts.len() - 1)];
+        let dir = unit(next - prev);
+        let perp = if left { perp_dec(dir) } else { perp_inc(dir) };
+        output.push(*pt + perp * distance);
+    }
+    LineString::new(output)
+}
+
+fn unit(c: Coord) -> Coord {
+    let len = (c.x * c.x + c.y * c.y).sqrt();
+    if len == 0.0 {
+        return Coord { x: 0.0, y: 0.0 };
+    }
+    Coord {
+        x: c.x / len,
+        y: c.y / len,
+    }
+}
+
+/// Rotates towards increasing angles
+fn perp_inc(c: Coord) -> Coord {
+    Coord { x: -c.y, y: c.x }
+}
+
+/// Rotates towards decreasing angles
+fn perp_dec(c: Coord) -> Coord {
+    Coord { x: c.y, y: -c.x }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_linestring() {
+        // Y points south, so the left of a road heading east is north
+        let road = LineString::from(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        let left = offset_linestring(&road, 2.0, true);
+        assert_eq!(left.0[0], Coord { x: 0.0, y: -2.0 });
+        assert_eq!(left.0[2], Coord { x: 12.0, y: 10.0 });
+        let right = offset_linestring(&road, 2.0, false);
+        assert_eq!(right.0[0], Coord { x: 0.0, y: 2.0 });
+        assert_eq!(right.0[2], Coord { x: 8.0, y: 10.0 });
+    }
+
+    #[test]
+    fn test_corner_point() {
+        let center = Coord { x: 0.0, y: 0.0 };
+        let east = Coord { x: 1.0, y: 0.0 };
+        let south = Coord { x: 0.0, y: 1.0 };
+        let west = Coord { x: -1.0, y: 0.0 };
+        let close = |a: Coord, b: Coord| (a.x - b.x).abs() < 1e-9 && (a.y - b.y).abs() < 1e-9;
+
+        // Between two roads at a right angle, the corner is 3m from both
+        assert!(close(
+            corner_point(center, east, south, 3.0),
+            Coord { x: 3.0, y: 3.0 }
+        ));
+        // On the outside of the same bend
+        assert!(close(
+            corner_point(center, south, east, 3.0),
+            Coord { x: -3.0, y: -3.0 }
+        ));
+        // Where a road continues straight on
+        assert!(close(
+            corner_point(center, east, west, 3.0),
+            Coord { x: 0.0, y: 3.0 }
+        ));
+        // Behind a dead-end
+        assert!(close(
+            corner_point(center, east, east, 3.0),
+            Coord { x: -3.0, y: 0.0 }
+        ));
+    }
+}
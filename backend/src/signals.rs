@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::{CrossingKind, MapModel, Settings};
+
+/// How to work out the wait at traffic signals
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SignalModel {
+    /// Use the same delay as every other crossing of that kind
+    Flat,
+    /// Assume people arrive at a random point in the cycle
+    Expected,
+    /// Assume people arrive just as the pedestrian phase ends
+    WorstCase,
+}
+
+/// Where a signal timing came from
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TimingSource {
+    Default,
+    Tags,
+    Override,
+}
+
+/// The cycle of one set of signals
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignalTiming {
+    /// Seconds for the whole cycle
+    pub cycle_s: f64,
+    /// Seconds in each cycle when people can start crossing
+    pub green_s: f64,
+    pub source: TimingSource,
+}
+
+impl SignalTiming {
+    /// Reads `crossing:signals:cycle_time` and `crossing:signals:green_time`, in seconds. Both
+    /// have to be present.
+    pub fn from_tags(tags: &Tags) -> Option<Self> {
+        let seconds = |key: &str| {
+            tags.get(key)
+                .and_then(|x| x.trim_end_matches('s').trim().parse::<f64>().ok())
+        };
+        Self::new(
+            seconds("crossing:signals:cycle_time")?,
+            seconds("crossing:signals:green_time")?,
+            TimingSource::Tags,
+        )
+    }
+
+    fn new(cycle_s: f64, green_s: f64, source: TimingSource) -> Option<Self> {
+        if cycle_s <= 0.0 || green_s < 0.0 || green_s > cycle_s {
+            return None;
+        }
+        Some(Self {
+            cycle_s,
+            green_s,
+            source,
+        })
+    }
+
+    /// Seconds spent waiting under a model, or `None` for the flat model
+    pub fn wait(&self, model: SignalModel) -> Option<f64> {
+        let red = self.cycle_s - self.green_s;
+        match model {
+            SignalModel::Flat => None,
+            // Arriving during the green phase doesn't wait. Otherwise, on average, half of the red
+            // phase is left.
+            SignalModel::Expected => Some(red * red / (2.0 * self.cycle_s)),
+            SignalModel::WorstCase => Some(red),
+        }
+    }
+}
+
+/// The usual signal cycle and pedestrian green time in a country, in seconds. These are rough
+/// guesses, meant to be adjusted in `Settings`.
+pub fn default_timing(country: Option<&str>) -> (f64, f64) {
+    match country {
+        // Long cycles with short walk phases
+        Some("US" | "CA") => (100.0, 20.0),
+        Some("DE" | "AT" | "CH") => (90.0, 15.0),
+        _ => (90.0, 20.0),
+    }
+}
+
+pub fn is_signalized(kind: CrossingKind) -> bool {
+    matches!(
+        kind,
+        CrossingKind::Signalized
+            | CrossingKind::Pelican
+            | CrossingKind::Puffin
+            | CrossingKind::Toucan
+    )
+}
+
+/// The wait at a signalized crossing, using its own timing if known, and the timing used. `None`
+/// for other crossings, or with the flat model.
+pub fn signal_wait(
+    kind: CrossingKind,
+    timing: Option<SignalTiming>,
+    settings: &Settings,
+) -> Option<(f64, SignalTiming)> {
+    if !is_signalized(kind) {
+        return None;
+    }
+    let timing = timing.or_else(|| {
+        SignalTiming::new(
+            settings.signal_cycle_s,
+            settings.signal_green_s,
+            TimingSource::Default,
+        )
+    })?;
+    Some((timing.wait(settings.signal_model)?, timing))
+}
+
+/// Parses CSV with a header, then `node_id,cycle_s,green_s` on each line
+pub fn parse_overrides(csv: &str) -> Result<HashMap<i64, SignalTiming>> {
+    let mut overrides = HashMap::new();
+    for (idx, line) in csv.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+        if parts.len() != 3 {
+            bail!("Line {} doesn't have 3 columns: {line}", idx + 1);
+        }
+        let node = parts[0].parse::<i64>()?;
+        let Some(timing) = SignalTiming::new(
+            parts[1].parse::<f64>()?,
+            parts[2].parse::<f64>()?,
+            TimingSource::Override,
+        ) else {
+            bail!("Line {} has an impossible timing: {line}", idx + 1);
+        };
+        overrides.insert(node, timing);
+    }
+    Ok(overrides)
+}
+
+impl MapModel {
+    /// Overrides the timing of signalized crossings from CSV keyed by OSM node ID. Returns how
+    /// many crossings matched.
+    pub fn set_signal_timings(&mut self, csv: &str) -> Result<usize> {
+        let overrides = parse_overrides(csv)?;
+        let mut matched = 0;
+        for crossing in &mut self.crossings {
+            let Some(timing) = crossing.node.and_then(|node| overrides.get(&node)) else {
+                continue;
+            };
+            if !is_signalized(crossing.kind) {
+                warn!(
+                    "Ignoring signal timing for {}, which is a {:?} crossing",
+                    crossing.url, crossing.kind
+                );
+                continue;
+            }
+            crossing.timing = Some(*timing);
+            matched += 1;
+        }
+        if matched < overrides.len() {
+            warn!(
+                "Only {matched} of {} signal timings matched a signalized crossing",
+                overrides.len()
+            );
+        }
+
+        self.crossing_shares =
+            crate::crossings::cluster_crossings(&self.graph, &self.road_kinds, &mut self.crossings);
+        Ok(matched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        let timing = SignalTiming::new(90.0, 30.0, TimingSource::Default).unwrap();
+        assert_eq!(timing.wait(SignalModel::Flat), None);
+        assert_eq!(timing.wait(SignalModel::Expected), Some(20.0));
+        assert_eq!(timing.wait(SignalModel::WorstCase), Some(60.0));
+        assert!(SignalTiming::new(30.0, 60.0, TimingSource::Default).is_none());
+
+        let overrides =
+            parse_overrides("node_id,cycle_s,green_s\n123,120,10\n\n456, 60, 20\n").unwrap();
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides[&456].cycle_s, 60.0);
+        assert!(parse_overrides("node_id,cycle_s,green_s\n123,10,20\n").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use graph::Graph;
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::grade_separation::{is_raised, is_sunken};
+use crate::{MapModel, qa};
+
+/// How a road climbs and descends, measured from `src_i` to `dst_i`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Slope {
+    /// Net gradient in percent, positive when the end is higher than the start
+    pub gradient: f64,
+    /// Meters climbed along the way
+    pub ascent_m: f64,
+    /// Meters descended along the way
+    pub descent_m: f64,
+    /// The steepest gradient between any two samples, in percent, uphill or downhill
+    pub max_slope: f64,
+}
+
+impl Slope {
+    /// From heights sampled along a road, as (meters from the start, height in meters)
+    pub fn from_samples(samples: &[(f64, f64)]) -> Self {
+        let mut slope = Self::default();
+        for pair in samples.windows(2) {
+            let (dist1, height1) = pair[0];
+            let (dist2, height2) = pair[1];
+            let rise = height2 - height1;
+            if rise > 0.0 {
+                slope.ascent_m += rise;
+            } else {
+                slope.descent_m -= rise;
+            }
+            if dist2 > dist1 {
+                slope.max_slope = slope.max_slope.max((rise / (dist2 - dist1) * 100.0).abs());
+            }
+        }
+        if let [(dist1, height1), .., (dist2, height2)] = samples {
+            let length = dist2 - dist1;
+            if length > 0.0 {
+                slope.gradient = (height2 - height1) / length * 100.0;
+            }
+        }
+        slope
+    }
+
+    /// From an `incline` tag with a value like `5%`, `-10%`, or `3°`, assuming a steady slope.
+    /// `up` and `down` don't say how steep, so they're ignored.
+    pub fn from_incline(tags: &Tags, length_m: f64) -> Option<Self> {
+        let value = tags.get("incline")?.trim();
+        let gradient = if let Some(percent) = value.strip_suffix('%') {
+            percent.trim().parse::<f64>().ok()?
+        } else if let Some(degrees) = value.strip_suffix('°') {
+            degrees.trim().parse::<f64>().ok()?.to_radians().tan() * 100.0
+        } else {
+            value.parse::<f64>().ok()?
+        };
+        let rise = gradient / 100.0 * length_m;
+        Some(Self {
+            gradient,
+            ascent_m: rise.max(0.0),
+            descent_m: (-rise).max(0.0),
+            max_slope: gradient.abs(),
+        })
+    }
+
+    /// Walking a road backwards turns climbs into descents
+    pub fn directed(self, forwards: bool) -> Self {
+        if forwards {
+            self
+        } else {
+            Self {
+                gradient: -self.gradient,
+                ascent_m: self.descent_m,
+                descent_m: self.ascent_m,
+                max_slope: self.max_slope,
+            }
+        }
+    }
+
+    /// Splits a road into the part going uphill and the part going downhill, as (meters, gradient
+    /// in percent). Where exactly it climbs isn't kept, so both parts are assumed to be equally
+    /// steep. For a steady slope, this is just the net gradient.
+    pub fn split(&self, length_m: f64) -> [(f64, f64); 2] {
+        let total = self.ascent_m + self.descent_m;
+        if total == 0.0 || length_m == 0.0 {
+            return [(length_m, 0.0), (0.0, 0.0)];
+        }
+        let gradient = total / length_m * 100.0;
+        let uphill = length_m * self.ascent_m / total;
+        [(uphill, gradient), (length_m - uphill, -gradient)]
+    }
+}
+
+/// Elevation data reads the ground, not a bridge deck or the floor of a tunnel, so only trust an
+/// `incline` tag there
+pub fn ignore_bridges_and_tunnels(graph: &Graph, slopes: &mut [Slope]) -> usize {
+    let mut count = 0;
+    for road in &graph.roads {
+        if is_raised(&road.osm_tags) || is_sunken(&road.osm_tags) {
+            slopes[road.id.0] =
+                Slope::from_incline(&road.osm_tags, road.length_meters).unwrap_or_default();
+            count += 1;
+        }
+    }
+    count
+}
+
+impl MapModel {
+    pub fn set_slopes(&mut self, mut slopes: Vec<Slope>) {
+        let count = ignore_bridges_and_tunnels(&self.graph, &mut slopes);
+        info!("Ignoring elevation on {count} bridges and tunnels");
+        // Replace issues from any earlier elevation data
+        self.issues
+            .retain(|issue| issue.kind != qa::IssueKind::UnusualSlope);
+        self.issues
+            .extend(qa::check_gradients(&self.graph, &slopes));
+        self.slopes = slopes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_slope() {
+        // Down 5m into an underpass and back up
+        let slope = Slope::from_samples(&[(0.0, 10.0), (50.0, 5.0), (100.0, 10.0)]);
+        assert_eq!(slope.gradient, 0.0);
+        assert_eq!(slope.ascent_m, 5.0);
+        assert_eq!(slope.descent_m, 5.0);
+        assert_eq!(slope.max_slope, 10.0);
+        assert_eq!(slope.split(100.0), [(50.0, 10.0), (50.0, -10.0)]);
+
+        let hill = Slope::from_samples(&[(0.0, 0.0), (100.0, 4.0)]);
+        assert_eq!(hill.split(100.0), [(100.0, 4.0), (0.0, -4.0)]);
+        let backwards = hill.directed(false);
+        assert_eq!(backwards.gradient, -4.0);
+        assert_eq!(backwards.split(100.0), [(0.0, 4.0), (100.0, -4.0)]);
+
+        let incline =
+            |value: &str| Slope::from_incline(&tags(vec![&format!("incline={value}")]), 100.0);
+        assert_eq!(incline("-10%").unwrap().descent_m, 10.0);
+        assert_eq!(incline("5").unwrap().ascent_m, 5.0);
+        assert!(incline("up").is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+/// Inclines steeper than this (in percent) are hard to use with a wheelchair, pushchair, or
+/// crutches
+const LIMITED_INCLINE: f64 = 5.0;
+/// And steeper than this is too much
+const MAX_INCLINE: f64 = 10.0;
+
+/// Can somebody who can't use steps get along a road?
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StepFree {
+    Usable,
+    /// Possible but harder, like `wheelchair=limited`, a rolled kerb, or a moderate incline
+    Limited(String),
+    /// Not possible, like steps without a ramp or a raised kerb
+    Unusable(String),
+}
+
+impl StepFree {
+    /// Judges a way by its own tags
+    pub fn from_way_tags(tags: &Tags) -> Self {
+        if let Some(x) = from_wheelchair_tag(tags) {
+            return x;
+        }
+
+        if tags.is("highway", "steps") {
+            if tags.is("ramp:wheelchair", "yes") {
+                return Self::Limited("steps with a wheelchair ramp".to_string());
+            }
+            if tags.is("ramp", "yes") {
+                return Self::Limited("steps with a ramp".to_string());
+            }
+            return Self::Unusable("steps".to_string());
+        }
+
+        if let Some(incline) = tags.get("incline") {
+            if let Some(percent) = parse_incline(incline) {
+                if percent.abs() > MAX_INCLINE {
+                    return Self::Unusable(format!("incline={incline}"));
+                }
+                if percent.abs() > LIMITED_INCLINE {
+                    return Self::Limited(format!("incline={incline}"));
+                }
+            }
+        }
+
+        Self::Usable
+    }
+
+    /// Judges a node along a way, like a kerb, barrier, or elevator. Kerbs only matter where they
+    /// are, so `is_kerb` says if the result comes from `kerb=*`.
+    pub fn from_node_tags(tags: &Tags) -> (Self, bool) {
+        if let Some(x) = from_wheelchair_tag(tags) {
+            return (x, false);
+        }
+
+        if let Some(barrier) = tags.get("barrier") {
+            if [
+                "stile",
+                "kissing_gate",
+                "turnstile",
+                "full-height_turnstile",
+            ]
+            .contains(&barrier.as_str())
+            {
+                return (Self::Unusable(format!("barrier={barrier}")), false);
+            }
+        }
+
+        if tags.is("kerb", "raised") {
+            return (Self::Unusable("kerb=raised".to_string()), true);
+        }
+        if tags.is("kerb", "rolled") {
+            return (Self::Limited("kerb=rolled".to_string()), true);
+        }
+
+        (Self::Usable, false)
+    }
+
+    /// Combines two judgements about the same road, keeping the worse one
+    pub fn worst(self, other: Self) -> Self {
+        if other.rank() > self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+
+    /// Why the road is limited or unusable
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Self::Usable => None,
+            Self::Limited(reason) | Self::Unusable(reason) => Some(reason),
+        }
+    }
+
+    fn rank(&self) -> usize {
+        match self {
+            Self::Usable => 0,
+            Self::Limited(_) => 1,
+            Self::Unusable(_) => 2,
+        }
+    }
+}
+
+/// An explicit `wheelchair=*` overrides everything else
+fn from_wheelchair_tag(tags: &Tags) -> Option<StepFree> {
+    match tags.get("wheelchair")?.as_str() {
+        "yes" | "designated" => Some(StepFree::Usable),
+        "limited" => Some(StepFree::Limited("wheelchair=limited".to_string())),
+        "no" => Some(StepFree::Unusable("wheelchair=no".to_string())),
+        _ => None,
+    }
+}
+
+/// Parses values like "8%", "-5%", or "3°", returning a percent. "up" and "down" return `None`.
+fn parse_incline(value: &str) -> Option<f64> {
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.trim().parse().ok();
+    }
+    if let Some(degrees) = value.strip_suffix('°') {
+        let degrees: f64 = degrees.trim().parse().ok()?;
+        return Some(100.0 * degrees.to_radians().tan());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_step_free() {
+        for (input, expected) in [
+            (vec!["highway=footway"], 0),
+            (vec!["highway=steps"], 2),
+            (vec!["highway=steps", "ramp=yes"], 1),
+            (vec!["highway=steps", "wheelchair=yes"], 0),
+            (vec!["highway=footway", "wheelchair=limited"], 1),
+            (vec!["highway=footway", "incline=-12%"], 2),
+            (vec!["highway=footway", "incline=6%"], 1),
+            (vec!["highway=footway", "incline=up"], 0),
+            (vec!["highway=footway", "incline=3°"], 0),
+        ] {
+            assert_eq!(
+                StepFree::from_way_tags(&tags(input.clone())).rank(),
+                expected,
+                "{input:?}"
+            );
+        }
+
+        for (input, expected, is_kerb) in [
+            (vec!["kerb=raised"], 2, true),
+            (vec!["kerb=lowered"], 0, false),
+            (vec!["barrier=kerb", "kerb=rolled"], 1, true),
+            (vec!["barrier=kissing_gate"], 2, false),
+            (vec!["barrier=kissing_gate", "wheelchair=yes"], 0, false),
+            (vec!["highway=elevator", "wheelchair=no"], 2, false),
+        ] {
+            let (actual, actual_is_kerb) = StepFree::from_node_tags(&tags(input.clone()));
+            assert_eq!(
+                (actual.rank(), actual_is_kerb),
+                (expected, is_kerb),
+                "{input:?}"
+            );
+        }
+    }
+}
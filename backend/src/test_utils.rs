@@ -0,0 +1,11 @@
+use utils::Tags;
+
+/// Builds tags from `key=value` strings
+pub fn tags(kv: Vec<&str>) -> Tags {
+    let mut tags = Tags::empty();
+    for pair in kv {
+        let parts = pair.split('=').collect::<Vec<_>>();
+        tags.insert(parts[0], parts[1]);
+    }
+    tags
+}
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{CrossingKind, Departure, Settings};
+
+/// Settings that change for part of the day, like rush hour or night. Anything not specified
+/// comes from the rest of `Settings`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimeBand {
+    pub name: String,
+    /// Empty means every day. A band continuing past midnight belongs to the day it starts.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    /// If this is before `start`, the band continues past midnight
+    pub end: NaiveTime,
+    /// Replaces the delay for these kinds of crossing
+    #[serde(default)]
+    pub crossing_delays: BTreeMap<CrossingKind, f64>,
+    /// Crossings of these kinds can't be used, like informal crossings at night
+    #[serde(default)]
+    pub disabled_crossings: BTreeSet<CrossingKind>,
+    #[serde(default)]
+    pub signal_cycle_s: Option<f64>,
+    #[serde(default)]
+    pub signal_green_s: Option<f64>,
+}
+
+impl TimeBand {
+    pub fn contains(&self, departure: Departure) -> bool {
+        let on_day = |day: Weekday| self.weekdays.is_empty() || self.weekdays.contains(&day);
+        if self.start <= self.end {
+            on_day(departure.weekday) && self.start <= departure.time && departure.time < self.end
+        } else if departure.time >= self.start {
+            on_day(departure.weekday)
+        } else {
+            departure.time < self.end && on_day(departure.weekday.pred())
+        }
+    }
+}
+
+impl Settings {
+    /// The first time band covering a departure
+    pub fn band_at(&self, departure: Departure) -> Option<&TimeBand> {
+        self.time_bands.iter().find(|band| band.contains(departure))
+    }
+
+    /// The settings in effect at a departure time, without any time bands left
+    pub fn at(&self, departure: Departure) -> Settings {
+        let mut settings = Settings {
+            time_bands: Vec::new(),
+            ..self.clone()
+        };
+        if let Some(band) = self.band_at(departure) {
+            settings
+                .crossing_delays
+                .extend(band.crossing_delays.clone());
+            settings
+                .disabled_crossings
+                .extend(band.disabled_crossings.iter().cloned());
+            if let Some(x) = band.signal_cycle_s {
+                settings.signal_cycle_s = x;
+            }
+            if let Some(x) = band.signal_green_s {
+                settings.signal_green_s = x;
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_bands() {
+        let time = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        let departure = |weekday, h| Departure {
+            weekday,
+            time: time(h),
+        };
+        let night = TimeBand {
+            name: "Friday night".to_string(),
+            weekdays: vec![Weekday::Fri],
+            start: time(22),
+            end: time(6),
+            crossing_delays: BTreeMap::from([(CrossingKind::Signalized, 10.0)]),
+            disabled_crossings: BTreeSet::from([CrossingKind::Informal]),
+            signal_cycle_s: None,
+            signal_green_s: None,
+        };
+        assert!(night.contains(departure(Weekday::Fri, 23)));
+        assert!(night.contains(departure(Weekday::Sat, 3)));
+        assert!(!night.contains(departure(Weekday::Fri, 3)));
+        assert!(!night.contains(departure(Weekday::Sat, 23)));
+        assert!(!night.contains(departure(Weekday::Fri, 12)));
+
+        let settings = Settings {
+            time_bands: vec![night],
+            ..Settings::uk()
+        };
+        let at_night = settings.at(departure(Weekday::Fri, 23));
+        assert_eq!(at_night.crossing_delays[&CrossingKind::Signalized], 10.0);
+        assert!(
+            at_night
+                .disabled_crossings
+                .contains(&CrossingKind::Informal)
+        );
+        assert!(at_night.time_bands.is_empty());
+        assert!(
+            settings.at(departure(Weekday::Mon, 7))
+                == Settings {
+                    time_bands: Vec::new(),
+                    ..settings.clone()
+                }
+        );
+    }
+}
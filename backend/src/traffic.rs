@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+/// Decides when a road is busy enough to be a severance, based on traffic speed and how many lanes
+/// somebody has to cross.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeveranceThresholds {
+    /// Roads with a tagged or assumed speed limit at or above this are severances
+    pub min_speed_kmph: f64,
+    /// Roads with at least this many lanes in total are severances. For one carriageway of a dual
+    /// carriageway, the lanes on the other carriageway count too.
+    pub min_lanes: usize,
+    /// Are dual carriageways always severances, no matter the speed or lanes?
+    pub dual_carriageway: bool,
+}
+
+impl Default for SeveranceThresholds {
+    fn default() -> Self {
+        Self {
+            // Just above the usual urban limits of 50 km/h or 30 mph
+            min_speed_kmph: 55.0,
+            min_lanes: 4,
+            dual_carriageway: true,
+        }
+    }
+}
+
+impl SeveranceThresholds {
+    pub fn is_severance(&self, tags: &Tags, country: Option<&str>) -> bool {
+        if let Some(speed) = speed_limit_kmph(tags, country) {
+            if speed >= self.min_speed_kmph {
+                return true;
+            }
+        }
+
+        let dual = is_dual_carriageway(tags);
+        if dual && self.dual_carriageway {
+            return true;
+        }
+
+        let mut lanes = tags
+            .get("lanes")
+            .and_then(|x| parse_lanes(x))
+            .unwrap_or(if is_oneway(tags) { 1 } else { 2 });
+        if dual {
+            // Assume the other carriageway is symmetric
+            lanes *= 2;
+        }
+        lanes >= self.min_lanes
+    }
+}
+
+/// The tagged speed limit, or a default based on the country and highway type
+pub fn speed_limit_kmph(tags: &Tags, country: Option<&str>) -> Option<f64> {
+    // If the limit differs by direction, use the faster one
+    let tagged = ["maxspeed", "maxspeed:forward", "maxspeed:backward"]
+        .into_iter()
+        .filter_map(|key| tags.get(key).and_then(|x| parse_maxspeed(x)))
+        .reduce(f64::max);
+    tagged.or_else(|| default_speed_kmph(country, tags.get("highway")?))
+}
+
+/// Parses values like "30", "50 km/h", "30 mph", "walk", or "GB:nsl_single", returning km/h.
+/// Values like "none", "signals", or "variable" don't say anything useful and return `None`.
+pub fn parse_maxspeed(value: &str) -> Option<f64> {
+    // If there are multiple values, just use the first
+    let value = value.split(';').next()?.trim();
+
+    if value == "walk" {
+        return Some(6.0);
+    }
+
+    if let Some(mph) = value.strip_suffix("mph") {
+        return mph.trim().parse::<f64>().ok().map(mph_to_kmph);
+    }
+    for suffix in ["km/h", "kmh", "kph"] {
+        if let Some(kmph) = value.strip_suffix(suffix) {
+            return kmph.trim().parse::<f64>().ok();
+        }
+    }
+    if let Ok(kmph) = value.parse::<f64>() {
+        return Some(kmph);
+    }
+
+    // Implicit limits, like "DE:urban" or "GB:nsl_dual"
+    let (country, kind) = value.split_once(':')?;
+    let uses_mph = matches!(country, "GB" | "US");
+    if let Some(zone) = kind.strip_prefix("zone") {
+        let limit = zone.trim_start_matches(':').parse::<f64>().ok()?;
+        return Some(if uses_mph { mph_to_kmph(limit) } else { limit });
+    }
+    match (country, kind) {
+        ("GB", "nsl_single") => Some(mph_to_kmph(60.0)),
+        ("GB", "nsl_dual" | "motorway") => Some(mph_to_kmph(70.0)),
+        (_, "urban") => Some(if uses_mph { mph_to_kmph(30.0) } else { 50.0 }),
+        (_, "rural" | "trunk") => Some(if uses_mph { mph_to_kmph(60.0) } else { 90.0 }),
+        (_, "motorway") => Some(if uses_mph { mph_to_kmph(65.0) } else { 120.0 }),
+        (_, "living_street") => Some(if uses_mph { mph_to_kmph(5.0) } else { 7.0 }),
+        _ => None,
+    }
+}
+
+/// Guesses the speed limit for a highway type in a country, specified as an ISO 3166-1 alpha-2
+/// code. These assume urban areas, since that's where most studies happen.
+pub fn default_speed_kmph(country: Option<&str>, highway: &str) -> Option<f64> {
+    let highway = highway.strip_suffix("_link").unwrap_or(highway);
+    let mph = match (country, highway) {
+        (Some("GB"), "motorway") => 70.0,
+        (Some("GB"), "trunk") => 40.0,
+        (Some("GB"), "primary" | "secondary" | "tertiary" | "unclassified") => 30.0,
+        (Some("GB"), "residential") => 20.0,
+        (Some("GB"), "service" | "living_street") => 10.0,
+
+        (Some("US"), "motorway") => 65.0,
+        (Some("US"), "trunk") => 55.0,
+        (Some("US"), "primary") => 45.0,
+        (Some("US"), "secondary") => 35.0,
+        (Some("US"), "tertiary") => 30.0,
+        (Some("US"), "unclassified" | "residential") => 25.0,
+        (Some("US"), "service" | "living_street") => 15.0,
+
+        _ => {
+            return match highway {
+                "motorway" => Some(100.0),
+                "trunk" => Some(80.0),
+                "primary" => Some(60.0),
+                "secondary" | "tertiary" | "unclassified" => Some(50.0),
+                "residential" => Some(30.0),
+                "service" => Some(20.0),
+                "living_street" | "pedestrian" => Some(10.0),
+                _ => None,
+            };
+        }
+    };
+    Some(mph_to_kmph(mph))
+}
+
+pub fn parse_lanes(value: &str) -> Option<usize> {
+    value.split(';').next()?.trim().parse().ok()
+}
+
+fn is_oneway(tags: &Tags) -> bool {
+    tags.is_any("oneway", vec!["yes", "-1"])
+        || tags.is_any("highway", vec!["motorway", "motorway_link"])
+        || tags.is("junction", "roundabout")
+}
+
+/// Is this one carriageway of a road split into two?
+fn is_dual_carriageway(tags: &Tags) -> bool {
+    if tags.is("dual_carriageway", "yes") {
+        return true;
+    }
+    // Big oneway roads (not ramps or roundabouts) are almost always one half of a dual carriageway
+    tags.is_any("oneway", vec!["yes", "-1"])
+        && !tags.is("junction", "roundabout")
+        && tags.is_any("highway", vec!["motorway", "trunk", "primary", "secondary"])
+}
+
+fn mph_to_kmph(mph: f64) -> f64 {
+    mph * 1.609344
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tags;
+
+    #[test]
+    fn test_parse_maxspeed() {
+        assert_eq!(parse_maxspeed("50"), Some(50.0));
+        assert_eq!(parse_maxspeed("50 km/h"), Some(50.0));
+        assert_eq!(parse_maxspeed("50kmh"), Some(50.0));
+        assert_eq!(parse_maxspeed("30 kph"), Some(30.0));
+        assert_close(parse_maxspeed("30 mph"), 48.28);
+        assert_close(parse_maxspeed("40mph"), 64.37);
+        assert_close(parse_maxspeed("20 mph;30 mph"), 32.19);
+        assert_close(parse_maxspeed("GB:nsl_single"), 96.56);
+        assert_close(parse_maxspeed("GB:zone20"), 32.19);
+        assert_eq!(parse_maxspeed("DE:urban"), Some(50.0));
+        assert_eq!(parse_maxspeed("DE:zone:30"), Some(30.0));
+        assert_eq!(parse_maxspeed("walk"), Some(6.0));
+        assert_eq!(parse_maxspeed("none"), None);
+        assert_eq!(parse_maxspeed("signals"), None);
+        assert_eq!(parse_maxspeed("fast mph"), None);
+    }
+
+    #[test]
+    fn test_is_severance() {
+        let thresholds = SeveranceThresholds::default();
+
+        // https://www.openstreetmap.org/way/41945235
+        assert!(thresholds.is_severance(&tags(vec!["highway=secondary", "maxspeed=40 mph"]), None));
+        // The assumed speed limit is only 30 mph, and the 2 lanes are easy to cross
+        assert!(
+            !thresholds.is_severance(&tags(vec!["highway=tertiary", "sidewalk=no"]), Some("US"))
+        );
+        assert!(thresholds.is_severance(
+            &tags(vec!["highway=tertiary", "maxspeed=30 mph", "lanes=4"]),
+            None
+        ));
+        assert!(thresholds.is_severance(
+            &tags(vec!["highway=primary", "oneway=yes", "maxspeed=20 mph"]),
+            None
+        ));
+        assert!(!thresholds.is_severance(
+            &tags(vec!["highway=residential", "oneway=yes", "lanes=2"]),
+            Some("GB")
+        ));
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "got {actual}, expected {expected}"
+        );
+    }
+}
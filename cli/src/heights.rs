@@ -0,0 +1,317 @@
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use elevation::GeoTiffElevation;
+
+/// Heights from any number of GeoTIFF and SRTM tiles. Where tiles overlap, SRTM tiles are checked
+/// first, then GeoTIFFs in the order given. Both are interpolated bilinearly between samples.
+pub struct Heights {
+    geotiffs: Vec<GeoTiff>,
+    hgts: Vec<Hgt>,
+}
+
+impl Heights {
+    /// Each path is a GeoTIFF in WGS84, an SRTM `.hgt` file, or a directory of them
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut heights = Self {
+            geotiffs: Vec::new(),
+            hgts: Vec::new(),
+        };
+        for path in paths {
+            if Path::new(path).is_dir() {
+                let mut files = Vec::new();
+                for entry in fs_err::read_dir(path)? {
+                    files.push(entry?.path());
+                }
+                // Make overlapping tiles resolve the same way every time
+                files.sort();
+                for file in files {
+                    if is_tile(&file) {
+                        heights.load_file(&file)?;
+                    }
+                }
+            } else if is_tile(Path::new(path)) {
+                heights.load_file(Path::new(path))?;
+            } else {
+                bail!("{path} isn't a .tif, .tiff, or .hgt file, or a directory of them");
+            }
+        }
+        if heights.geotiffs.is_empty() && heights.hgts.is_empty() {
+            bail!("No elevation tiles found in {paths:?}");
+        }
+        log::info!(
+            "Read {} GeoTIFF and {} SRTM elevation tiles",
+            heights.geotiffs.len(),
+            heights.hgts.len()
+        );
+        Ok(heights)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        log::info!("Reading elevation data from {}", path.display());
+        if extension(path) == "hgt" {
+            self.hgts.push(Hgt::load(path)?);
+        } else {
+            self.geotiffs.push(GeoTiff::load(path)?);
+        }
+        Ok(())
+    }
+
+    /// Height in meters, or `None` if no tile covers a point or the data there is void
+    pub fn get(&mut self, lon: f64, lat: f64) -> Option<f64> {
+        for hgt in &self.hgts {
+            if let Some(height) = hgt.get(lon, lat) {
+                return Some(height);
+            }
+        }
+        for geotiff in &mut self.geotiffs {
+            if let Some(height) = geotiff.get(lon, lat) {
+                return Some(height);
+            }
+        }
+        None
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn is_tile(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "tif" | "tiff" | "hgt")
+}
+
+/// One SRTM tile, covering one degree square. The file holds rows of big-endian heights from north
+/// to south, and the name gives the south-west corner, like `N51W001.hgt`.
+struct Hgt {
+    south: f64,
+    west: f64,
+    /// Samples along each side; 1201 for 3 arc-seconds or 3601 for 1 arc-second
+    size: usize,
+    data: Vec<i16>,
+}
+
+/// Marks missing data
+const VOID: i16 = -32768;
+
+impl Hgt {
+    fn load(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or("")
+            .to_uppercase();
+        let Some((south, west)) = parse_corner(&name) else {
+            bail!("Can't tell where {} is from its name", path.display());
+        };
+
+        let bytes = fs_err::read(path)?;
+        let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+        if size < 2 || size * size * 2 != bytes.len() {
+            bail!("{} isn't a square SRTM tile", path.display());
+        }
+        let data = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        Ok(Self {
+            south,
+            west,
+            size,
+            data,
+        })
+    }
+
+    /// Bilinearly interpolates between the 4 samples around a point. `None` outside the tile, or
+    /// if any of those samples is void.
+    fn get(&self, lon: f64, lat: f64) -> Option<f64> {
+        let max = (self.size - 1) as f64;
+        let x = (lon - self.west) * max;
+        let y = (self.south + 1.0 - lat) * max;
+        if !(0.0..=max).contains(&x) || !(0.0..=max).contains(&y) {
+            return None;
+        }
+        // On the east or south edge, use the last cell
+        let col = (x.floor() as usize).min(self.size - 2);
+        let row = (y.floor() as usize).min(self.size - 2);
+        let (dx, dy) = (x - col as f64, y - row as f64);
+
+        let sample = |row: usize, col: usize| {
+            let height = self.data[row * self.size + col];
+            if height == VOID {
+                None
+            } else {
+                Some(f64::from(height))
+            }
+        };
+        Some(bilinear(
+            [
+                [sample(row, col)?, sample(row, col + 1)?],
+                [sample(row + 1, col)?, sample(row + 1, col + 1)?],
+            ],
+            dx,
+            dy,
+        ))
+    }
+}
+
+/// One GeoTIFF in WGS84. The elevation crate reads the pixel containing a point, so this finds the
+/// 4 pixels around it from the tile's georeferencing tags.
+struct GeoTiff {
+    elevation: GeoTiffElevation<BufReader<fs_err::File>>,
+    /// The north-west corner of the tile and the size of each pixel, in degrees. `None` if the
+    /// tags are missing, so only the nearest pixel is used.
+    grid: Option<Grid>,
+}
+
+#[derive(Clone, Copy)]
+struct Grid {
+    west: f64,
+    north: f64,
+    pixel_width: f64,
+    pixel_height: f64,
+}
+
+impl GeoTiff {
+    fn load(path: &Path) -> Result<Self> {
+        let grid = read_grid(path)?;
+        if grid.is_none() {
+            log::warn!(
+                "{} has no pixel scale or tiepoint, so won't be interpolated",
+                path.display()
+            );
+        }
+        Ok(Self {
+            elevation: GeoTiffElevation::new(BufReader::new(fs_err::File::open(path)?)),
+            grid,
+        })
+    }
+
+    /// Bilinearly interpolates between the centers of the 4 pixels around a point. Along the
+    /// edges of the tile, where some of those are missing, uses the nearest pixel.
+    fn get(&mut self, lon: f64, lat: f64) -> Option<f64> {
+        let nearest = f64::from(
+            self.elevation
+                .get_height_for_lon_lat(lon as f32, lat as f32)?,
+        );
+        let Some(grid) = self.grid else {
+            return Some(nearest);
+        };
+        let x = (lon - grid.west) / grid.pixel_width - 0.5;
+        let y = (grid.north - lat) / grid.pixel_height - 0.5;
+        let (col, row) = (x.floor(), y.floor());
+        let (dx, dy) = (x - col, y - row);
+
+        Some(match self.pixels_around(grid, row, col) {
+            Some(corners) => bilinear(corners, dx, dy),
+            None => nearest,
+        })
+    }
+
+    /// The heights at the centers of a pixel and its neighbours to the east, south, and south-east
+    fn pixels_around(&mut self, grid: Grid, row: f64, col: f64) -> Option<[[f64; 2]; 2]> {
+        let mut sample = |row: f64, col: f64| {
+            let lon = grid.west + (col + 0.5) * grid.pixel_width;
+            let lat = grid.north - (row + 0.5) * grid.pixel_height;
+            self.elevation
+                .get_height_for_lon_lat(lon as f32, lat as f32)
+                .map(f64::from)
+        };
+        Some([
+            [sample(row, col)?, sample(row, col + 1.0)?],
+            [sample(row + 1.0, col)?, sample(row + 1.0, col + 1.0)?],
+        ])
+    }
+}
+
+/// Reads where a GeoTIFF is from its ModelPixelScale and ModelTiepoint tags
+fn read_grid(path: &Path) -> Result<Option<Grid>> {
+    let mut decoder = tiff::decoder::Decoder::new(BufReader::new(fs_err::File::open(path)?))?;
+    let (Ok(scale), Ok(tiepoint)) = (
+        decoder.get_tag_f64_vec(tiff::tags::Tag::ModelPixelScaleTag),
+        decoder.get_tag_f64_vec(tiff::tags::Tag::ModelTiepointTag),
+    ) else {
+        return Ok(None);
+    };
+    if scale.len() < 2 || tiepoint.len() < 6 || scale[0] <= 0.0 || scale[1] <= 0.0 {
+        return Ok(None);
+    }
+    // The tiepoint maps a raster position (i, j) to a point (x, y)
+    Ok(Some(Grid {
+        west: tiepoint[3] - tiepoint[0] * scale[0],
+        north: tiepoint[4] + tiepoint[1] * scale[1],
+        pixel_width: scale[0],
+        pixel_height: scale[1],
+    }))
+}
+
+/// Interpolates between heights at the corners of a cell, given as rows from north to south, at a
+/// point `dx` of the way east and `dy` of the way south
+fn bilinear(corners: [[f64; 2]; 2], dx: f64, dy: f64) -> f64 {
+    let [[nw, ne], [sw, se]] = corners;
+    let north = nw * (1.0 - dx) + ne * dx;
+    let south = sw * (1.0 - dx) + se * dx;
+    north * (1.0 - dy) + south * dy
+}
+
+/// Parses names like `N51W001` into (south, west)
+fn parse_corner(name: &str) -> Option<(f64, f64)> {
+    if name.len() != 7 || !name.is_ascii() {
+        return None;
+    }
+    let lat = name[1..3].parse::<f64>().ok()?;
+    let lon = name[4..7].parse::<f64>().ok()?;
+    let lat = match &name[0..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => return None,
+    };
+    let lon = match &name[3..4] {
+        "E" => lon,
+        "W" => -lon,
+        _ => return None,
+    };
+    Some((lat, lon))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_corner() {
+        assert_eq!(parse_corner("N51W001"), Some((51.0, -1.0)));
+        assert_eq!(parse_corner("S34E151"), Some((-34.0, 151.0)));
+        assert_eq!(parse_corner("N51X001"), None);
+        assert_eq!(parse_corner("N51W01"), None);
+        assert_eq!(parse_corner("N51W001.hgt"), None);
+    }
+
+    #[test]
+    fn test_hgt_get() {
+        // A tiny 3x3 tile, rising by 10m per sample to the east and 100m per sample to the south,
+        // with one void
+        let hgt = Hgt {
+            south: 51.0,
+            west: -1.0,
+            size: 3,
+            data: vec![0, 10, 20, 100, 110, 120, 200, 210, VOID],
+        };
+        // The north-west corner
+        assert_eq!(hgt.get(-1.0, 52.0), Some(0.0));
+        // Between samples
+        assert_eq!(hgt.get(-0.75, 51.75), Some(55.0));
+        // The east edge, using the last cell
+        assert_eq!(hgt.get(0.0, 51.75), Some(70.0));
+        // Outside the tile
+        assert_eq!(hgt.get(0.5, 51.5), None);
+        assert_eq!(hgt.get(-0.5, 50.9), None);
+        // Next to the void
+        assert_eq!(hgt.get(-0.25, 51.25), None);
+        assert_eq!(hgt.get(-0.75, 51.25), Some(155.0));
+    }
+}
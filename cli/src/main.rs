@@ -1,5 +1,11 @@
 use anyhow::{Result, bail};
 use clap::Parser;
+use geo::{Densify, Distance, Euclidean};
+
+mod heights;
+
+/// How far apart to sample elevation along each road, in meters
+const SAMPLE_SPACING_M: f64 = 10.0;
 
 #[derive(Parser)]
 struct Args {
@@ -7,9 +13,54 @@ struct Args {
     #[arg(long)]
     input: String,
 
-    /// Optional path to a .geotiff file in WGS84 with height in meters
+    /// The name of a built-in profile, a path to a JSON file with road classification rules, or
+    /// "auto" to pick a profile based on how sidewalks are mapped
+    #[arg(long, default_value = "USA")]
+    profile: String,
+
+    /// With `--profile auto`, optionally write GeoJSON explaining the recommendation here
+    #[arg(long)]
+    recommendation_output: Option<String>,
+
+    /// Optional elevation data with height in meters: a GeoTIFF in WGS84, an SRTM .hgt file, or a
+    /// directory of them. Can be repeated. Roads without coverage use `incline` tags, or are
+    /// treated as flat.
+    #[arg(long)]
+    elevation: Vec<String>,
+
+    /// Optionally write GeoJSON listing roads without elevation coverage here
+    #[arg(long)]
+    elevation_report: Option<String>,
+
+    /// Optional path to CSV overriding the timing of signalized crossings, with `node_id`,
+    /// `cycle_s`, and `green_s` columns
     #[arg(long)]
-    elevation: Option<String>,
+    signal_timings: Option<String>,
+
+    /// Optionally write a GeoJSON report of possible tagging problems here
+    #[arg(long)]
+    qa_output: Option<String>,
+
+    /// Optionally route between two points, given as `lon1,lat1,lon2,lat2`, for each persona
+    #[arg(long)]
+    route: Option<String>,
+
+    /// Optionally calculate an isochrone from a point, given as `lon,lat`, for each persona
+    #[arg(long)]
+    isochrone: Option<String>,
+
+    /// The time limit for `--isochrone`
+    #[arg(long, default_value_t = 15)]
+    isochrone_mins: u64,
+
+    /// For `--route` and `--isochrone`, a comma-separated list of personas, like
+    /// `AbleBodiedAdult,Wheelchair`. Every persona by default.
+    #[arg(long)]
+    persona: Option<String>,
+
+    /// Where to write GeoJSON comparing personas for `--route` or `--isochrone`
+    #[arg(long)]
+    persona_output: Option<String>,
 
     /// Map model output file to write
     #[arg(long)]
@@ -20,9 +71,85 @@ fn main() -> Result<()> {
     simple_logger::init_with_level(log::Level::Info).unwrap();
     let args = Args::parse();
 
-    let mut map = backend::MapModel::create(&fs_err::read(&args.input)?, backend::Profile::USA)?;
-    if let Some(path) = &args.elevation {
-        map.set_gradients(read_gradients(path, map.get_graph())?);
+    if args.route.is_some() && args.isochrone.is_some() {
+        bail!("Only one of --route and --isochrone can be used at a time");
+    }
+    if (args.route.is_some() || args.isochrone.is_some()) != args.persona_output.is_some() {
+        bail!("--persona-output must be used with one of --route or --isochrone");
+    }
+    let personas = match &args.persona {
+        Some(list) => list
+            .split(',')
+            .map(backend::Persona::parse)
+            .collect::<Result<Vec<_>>>()?,
+        None => backend::Persona::all(),
+    };
+
+    let input_bytes = fs_err::read(&args.input)?;
+    let profile = if args.profile == "auto" {
+        let recommendation = backend::recommend_profile(&input_bytes)?;
+        if recommendation.is_mixed() {
+            let mix = recommendation
+                .mix()
+                .into_iter()
+                .map(|(profile, fraction)| format!("{profile} {:.0}%", 100.0 * fraction))
+                .collect::<Vec<_>>()
+                .join(", ");
+            log::warn!(
+                "Sidewalk mapping varies across the area ({mix}); using {} everywhere. \
+                 See the zones in --recommendation-output.",
+                recommendation.profile
+            );
+        }
+        if let Some(path) = &args.recommendation_output {
+            fs_err::write(path, serde_json::to_string(&recommendation.evidence)?)?;
+            log::info!("Wrote {path}");
+        }
+        backend::Profile::builtin(&recommendation.profile)?
+    } else if backend::Profile::builtin_names().contains(&args.profile.as_str()) {
+        backend::Profile::builtin(&args.profile)?
+    } else {
+        backend::Profile::load(&fs_err::read_to_string(&args.profile)?)?
+    };
+    let mut map = backend::MapModel::create(&input_bytes, profile)?;
+    if !args.elevation.is_empty() {
+        let slopes = read_slopes(
+            &args.elevation,
+            map.get_graph(),
+            args.elevation_report.as_deref(),
+        )?;
+        map.set_slopes(slopes);
+    }
+    if let Some(path) = &args.signal_timings {
+        let matched = map.set_signal_timings(&fs_err::read_to_string(path)?)?;
+        log::info!("Overrode the timing of {matched} signalized crossings from {path}");
+    }
+
+    if let Some(path) = &args.qa_output {
+        fs_err::write(path, map.qa_report()?)?;
+        log::info!("Wrote {path}");
+    }
+
+    if let Some(path) = &args.persona_output {
+        let mercator = &map.get_graph().mercator;
+        let departure = backend::Departure::default();
+        let gj = match (&args.route, &args.isochrone) {
+            (Some(route), None) => {
+                let pts = parse_points(route, 2)?;
+                let (start, end) = (
+                    mercator.pt_to_mercator(pts[0]),
+                    mercator.pt_to_mercator(pts[1]),
+                );
+                map.compare_personas_route(start, end, &personas, departure)
+            }
+            (None, Some(isochrone)) => {
+                let pt = mercator.pt_to_mercator(parse_points(isochrone, 1)?[0]);
+                map.compare_personas_isochrone(pt, args.isochrone_mins, &personas, departure)
+            }
+            _ => bail!("--persona-output must be used with one of --route or --isochrone"),
+        };
+        fs_err::write(path, serde_json::to_string(&gj)?)?;
+        log::info!("Wrote {path}");
     }
 
     let writer = std::io::BufWriter::new(fs_err::File::create(&args.output)?);
@@ -32,36 +159,90 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn read_gradients(path: &str, graph: &graph::Graph) -> Result<Vec<f64>> {
-    log::info!("Reading elevation data from {path}");
-    let mut geotiff =
-        elevation::GeoTiffElevation::new(std::io::BufReader::new(fs_err::File::open(path)?));
-    let mut gradients = Vec::new();
+/// Parses `lon,lat` pairs separated by commas
+fn parse_points(input: &str, expected: usize) -> Result<Vec<geo::Coord>> {
+    let numbers = input
+        .split(',')
+        .map(|x| x.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() != expected * 2 {
+        bail!("Expected {expected} lon,lat points, but got {input}");
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| geo::Coord {
+            x: pair[0],
+            y: pair[1],
+        })
+        .collect())
+}
+
+fn read_slopes(
+    paths: &[String],
+    graph: &graph::Graph,
+    report_path: Option<&str>,
+) -> Result<Vec<backend::Slope>> {
+    let mut heights = heights::Heights::load(paths)?;
+    let mut slopes = Vec::new();
+    let mut uncovered = Vec::new();
+    let mut from_incline = 0;
     for road in &graph.roads {
-        // TODO This only checks the start and end point
-        let pt1 = graph
-            .mercator
-            .pt_to_wgs84(*road.linestring.coords().next().unwrap());
-        let pt2 = graph
-            .mercator
-            .pt_to_wgs84(*road.linestring.coords().last().unwrap());
-
-        let Some(height1) = geotiff.get_height_for_lon_lat(pt1.x as f32, pt1.y as f32) else {
-            bail!("Couldn't get height for {pt1:?}");
-        };
-        let Some(height2) = geotiff.get_height_for_lon_lat(pt2.x as f32, pt2.y as f32) else {
-            bail!("Couldn't get height for {pt2:?}");
-        };
+        // Sample along the whole road, so dips and rises in the middle count
+        let mut samples = Vec::new();
+        let mut dist = 0.0;
+        let mut last: Option<geo::Coord> = None;
+        let mut covered = true;
+        for pt in Euclidean.densify(&road.linestring, SAMPLE_SPACING_M).0 {
+            if let Some(last) = last {
+                dist += Euclidean.distance(last, pt);
+            }
+            last = Some(pt);
 
-        let slope = (height2 - height1) / (road.length_meters as f32) * 100.0;
-        if slope < -20.0 || slope > 20.0 {
-            log::warn!(
-                "Unusual slope {slope}, going from {height1} to {height2} over {} meters",
-                road.length_meters
-            );
+            let wgs84 = graph.mercator.pt_to_wgs84(pt);
+            let Some(height) = heights.get(wgs84.x, wgs84.y) else {
+                covered = false;
+                break;
+            };
+            samples.push((dist, height));
         }
-        // TODO Check it's in a reasonable range
-        gradients.push(slope.into());
+
+        // Unusual slopes are reported by the QA checks, and bridges and tunnels are ignored
+        if covered {
+            slopes.push(backend::Slope::from_samples(&samples));
+            continue;
+        }
+        // A gap anywhere along the road makes its climbs unreliable
+        let (slope, fallback) =
+            match backend::Slope::from_incline(&road.osm_tags, road.length_meters) {
+                Some(slope) => {
+                    from_incline += 1;
+                    (slope, "incline tag")
+                }
+                None => (backend::Slope::default(), "flat"),
+            };
+        slopes.push(slope);
+        let mut f = graph.mercator.to_wgs84_gj(&road.linestring);
+        f.set_property("url", road.way.to_string());
+        f.set_property("fallback", fallback);
+        uncovered.push(f);
+    }
+
+    if !uncovered.is_empty() {
+        log::warn!(
+            "{} of {} roads aren't fully covered by elevation data. {} use incline tags instead, \
+             and the rest are treated as flat",
+            uncovered.len(),
+            graph.roads.len(),
+            from_incline
+        );
+    }
+    if let Some(path) = report_path {
+        let gj = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": uncovered,
+        });
+        fs_err::write(path, serde_json::to_string(&gj)?)?;
+        log::info!("Wrote {path}");
     }
-    Ok(gradients)
+    Ok(slopes)
 }
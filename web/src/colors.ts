@@ -1,16 +1,37 @@
 export const kindToColor = {
   Footway: "black",
   WithTraffic: "grey",
+  PedestrianArea: "purple",
+  Elevator: "blue",
+  Escalator: "blue",
   "Crossing(Signalized)": "green",
   "Crossing(Zebra)": "green",
   "Crossing(Other)": "green",
+  "Crossing(Pelican)": "green",
+  "Crossing(Puffin)": "green",
+  "Crossing(Toucan)": "green",
+  "Crossing(Marked)": "green",
+  "Crossing(Refuge)": "green",
+  "Crossing(Unmarked)": "green",
+  "Crossing(Informal)": "green",
+  "Crossing(Footbridge)": "green",
+  "Crossing(Underpass)": "green",
   Severance: "red",
 };
 
-export const crossingColors = {
+export const crossingColors: Record<string, string> = {
   Signalized: "yellow",
+  Pelican: "yellow",
+  Puffin: "gold",
+  Toucan: "khaki",
   Zebra: "white",
+  Marked: "lightgrey",
+  Refuge: "cyan",
+  Unmarked: "tan",
+  Informal: "pink",
   Other: "brown",
+  Footbridge: "orange",
+  Underpass: "purple",
 };
 
 export const colorScale = [
@@ -32,3 +53,7 @@ export let gradientColors = [
   "#000000",
 ];
 export let gradientLimits = [0, 3, 5, 8, 10, 20, 100];
+
+// Comfort scores go from 0 (worst) to 1
+export let comfortColors = ["#A80000", "#E60000", "#FFAA00", "#7FB800", "#267300"];
+export let comfortLimits = [0, 0.2, 0.4, 0.6, 0.8, 1];
@@ -21,13 +21,41 @@ export interface RouteGJ extends FeatureCollection {
   active_duration_s: number;
   waiting_duration_s: number;
   directions: Step[];
+  departure: Departure;
+  time_band: string | null;
+  signal_model: SignalModel;
+  avoided_nearby: AvoidedRoad[];
+  // From 0 to 1, weighted by length
+  comfort: number;
+  max_traffic_stress: number;
+}
+
+export interface AvoidedRoad {
+  name?: string;
+  way: string;
+  reason: string;
 }
 
 export interface Step {
   name?: string;
   way: string;
   kind: string;
+  access: string;
+  closed: boolean;
+  unusable: string | null;
   layer: string;
+  level: string | null;
+  crossing_wait_s: number | null;
+  signal_timing: SignalTiming | null;
+  comfort: number;
+  traffic_stress: number;
+  comfort_reasons: string[];
+}
+
+export interface SignalTiming {
+  cycle_s: number;
+  green_s: number;
+  source: "Default" | "Tags" | "Override";
 }
 
 export let model: Writable<backend.MapModel | null> = writable(null);
@@ -53,7 +81,12 @@ export let profile = urlState({
   name: "profile",
   defaultValue: "USA",
   stringify: (x) => x,
-  parse: enumUrl(["USA", "SidewalksOnHighways", "SeparateWays"]),
+  parse: enumUrl([
+    "USA",
+    "SidewalksOnHighways",
+    "SeparateWays",
+    "TrafficSpeed",
+  ]),
 });
 
 export let routeA: Writable<Position | null> = writable(null);
@@ -64,22 +97,95 @@ export let isochroneMins: Writable<number> = writable(15);
 
 export type Position = [number, number];
 
+export interface Departure {
+  weekday: string;
+  // HH:MM:SS
+  time: string;
+}
+
+export let departure: Writable<Departure> = writable({
+  weekday: "Mon",
+  time: "07:00:00",
+});
+// Used to compare time periods in score mode
+export let departure2: Writable<Departure> = writable({
+  weekday: "Mon",
+  time: "23:00:00",
+});
+
+export interface TimeBand {
+  name: string;
+  // Empty means every day
+  weekdays: string[];
+  start: string;
+  // Before start means the band continues past midnight
+  end: string;
+  crossing_delays: Record<string, number>;
+  disabled_crossings: string[];
+  signal_cycle_s?: number | null;
+  signal_green_s?: number | null;
+}
+
+export type SignalModel = "Flat" | "Expected" | "WorstCase";
+
 export interface Settings {
   obey_crossings: boolean;
   base_speed_mph: number;
   use_gradient: boolean;
-  delay_signalized: number;
-  delay_zebra: number;
-  delay_other: number;
+  // Multiplies the gradient's effect on speed
+  gradient_factor: number;
+  // Seconds per CrossingKind
+  crossing_delays: Record<string, number>;
+  extra_stage_factor: number;
+  comfort_factor: number;
+  signal_model: SignalModel;
+  signal_cycle_s: number;
+  signal_green_s: number;
+  permissive_penalty: number;
+  destination_penalty: number;
+  step_free: boolean;
+  limited_penalty: number;
+  elevator_delay: number;
+  escalator_speed_mph: number;
+  disabled_crossings: string[];
+  time_bands: TimeBand[];
 }
 
+// Replaced by the model's defaults for its country once loaded
+const defaultCrossingDelays = {
+  Signalized: 30,
+  Pelican: 30,
+  Puffin: 25,
+  Toucan: 30,
+  Zebra: 0,
+  Marked: 5,
+  Refuge: 10,
+  Unmarked: 15,
+  Informal: 20,
+  Other: 10,
+  Footbridge: 60,
+  Underpass: 40,
+};
+
 export let settings: Writable<Settings> = writable({
   obey_crossings: true,
   base_speed_mph: 3,
   use_gradient: false,
-  delay_signalized: 30,
-  delay_zebra: 0,
-  delay_other: 10,
+  gradient_factor: 1,
+  crossing_delays: { ...defaultCrossingDelays },
+  extra_stage_factor: 0.5,
+  comfort_factor: 0,
+  signal_model: "Flat",
+  signal_cycle_s: 90,
+  signal_green_s: 20,
+  permissive_penalty: 1.5,
+  destination_penalty: 600,
+  step_free: false,
+  limited_penalty: 2,
+  elevator_delay: 45,
+  escalator_speed_mph: 1.5,
+  disabled_crossings: [],
+  time_bands: [],
 });
 
 // Used in isochrone mode sometimes
@@ -87,7 +193,19 @@ export let settings2: Writable<Settings> = writable({
   obey_crossings: false,
   base_speed_mph: 3,
   use_gradient: false,
-  delay_signalized: 30,
-  delay_zebra: 0,
-  delay_other: 10,
+  gradient_factor: 1,
+  crossing_delays: { ...defaultCrossingDelays },
+  extra_stage_factor: 0.5,
+  comfort_factor: 0,
+  signal_model: "Flat",
+  signal_cycle_s: 90,
+  signal_green_s: 20,
+  permissive_penalty: 1.5,
+  destination_penalty: 600,
+  step_free: false,
+  limited_penalty: 2,
+  elevator_delay: 45,
+  escalator_speed_mph: 1.5,
+  disabled_crossings: [],
+  time_bands: [],
 });